@@ -0,0 +1,137 @@
+// Trains a net on recorded Human runs (see `imitation`) and writes it out as a
+// brain file, JSON for a `.json` path and the compact binary format otherwise.
+// The net gets the config's layer sizes and activations. Directories are read
+// for every `.replay` file in them. Hand the result to `flappy-train --from`,
+// or load it in the game, to evolve a population from it.
+//
+// Usage: flappy-imitate [--config FILE] [--seed N] [--out PATH] REPLAY...
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flappy_rust::brain::Brain;
+use flappy_rust::brain_file::save_brain;
+use flappy_rust::config::Config;
+use flappy_rust::imitation::imitate;
+use flappy_rust::replay::Replay;
+use flappy_rust::resources::SimRng;
+
+const USAGE: &str = "Usage: flappy-imitate [--config FILE] [--seed N] [--out PATH] REPLAY...";
+
+struct ImitateArgs {
+    config: Option<PathBuf>,
+    seed: Option<u64>,
+    out: PathBuf,
+    replays: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<ImitateArgs, String> {
+    let mut parsed = ImitateArgs {
+        config: None,
+        seed: None,
+        out: PathBuf::from("imitated_brain.json"),
+        replays: Vec::new(),
+    };
+
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if !flag.starts_with("--") {
+            parsed.replays.push(PathBuf::from(flag));
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--config" => parsed.config = Some(PathBuf::from(value)),
+            "--seed" => {
+                parsed.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid value for {}: {}", flag, value))?,
+                )
+            }
+            "--out" => parsed.out = PathBuf::from(value),
+            _ => return Err(format!("Unknown argument: {}", flag)),
+        }
+    }
+
+    if parsed.replays.is_empty() {
+        return Err("No replays given".to_string());
+    }
+    Ok(parsed)
+}
+
+// `path` itself, or the replays in it if it's a directory
+fn replay_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let entries = fs::read_dir(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "replay"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = match Config::load_or_default(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to load config: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut replays = Vec::new();
+    for path in args.replays.iter() {
+        let files = replay_files(path).unwrap_or_else(|err| {
+            eprintln!("Failed to read {}", err);
+            std::process::exit(1);
+        });
+        for file in files {
+            match Replay::load(&file) {
+                Ok(replay) => replays.push(replay),
+                Err(err) => {
+                    eprintln!("Failed to load replay {}: {}", file.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let seed = args.seed.or(config.seed).unwrap_or(0);
+    println!("Imitating {} replays (seed {})", replays.len(), seed);
+
+    let mut rng = SimRng::new(seed);
+    let (net, losses) = match imitate(&replays, &config.training, &config.imitation, &mut rng.evolution) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Training failed: {}", err);
+            std::process::exit(1);
+        }
+    };
+    for (epoch, loss) in losses.iter().enumerate() {
+        println!("Epoch {}: loss {:.5}", epoch + 1, loss);
+    }
+
+    let brain = Brain::Net(net);
+    if let Err(err) = save_brain(&brain, &args.out) {
+        eprintln!("Failed to write {}: {}", args.out.display(), err);
+        std::process::exit(1);
+    }
+    println!("{} saved to {}", brain, args.out.display());
+}
@@ -0,0 +1,43 @@
+// Exports a brain file's net to an ONNX model (see `onnx`) for use outside the
+// game. NEAT genomes and nets with recurrent layers can't be exported.
+//
+// Usage: flappy-onnx BRAIN OUT
+
+use std::path::PathBuf;
+
+use flappy_rust::brain::Brain;
+use flappy_rust::brain_file::load_brain;
+use flappy_rust::onnx::save_onnx;
+
+const USAGE: &str = "Usage: flappy-onnx BRAIN OUT";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let [brain_path, out] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    };
+    let (brain_path, out) = (PathBuf::from(brain_path), PathBuf::from(out));
+
+    let brain = match load_brain(&brain_path) {
+        Ok(brain) => brain,
+        Err(err) => {
+            eprintln!("Failed to load brain {}: {}", brain_path.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let Brain::Net(net) = &brain else {
+        eprintln!("Only nets can be exported, {} holds a NEAT genome", brain_path.display());
+        std::process::exit(1);
+    };
+
+    if let Err(err) = save_onnx(net, &out) {
+        eprintln!("Failed to write {}: {}", out.display(), err);
+        std::process::exit(1);
+    }
+    println!("{} exported to {}", brain, out.display());
+}
@@ -0,0 +1,211 @@
+// Headless trainer: evolves birds under MinimalPlugins (no window, sprites or audio)
+// and writes the best brain out once the requested generations are done (JSON
+// for a `.json` path, the compact binary format otherwise). With
+// `--checkpoint-every N` the whole run is saved every N generations so it can be
+// picked up again with `--resume`, which carries on with the checkpoint's
+// config and RNG. Otherwise `--population` and `--seed` override the config
+// file. `--from` starts the population from a brain file, such as one
+// trained by flappy-imitate, instead of random brains. Training ends early
+// with the winner saved once the game counts as solved, see `[training.solved]`.
+//
+// Usage: flappy-train [--config FILE] [--generations N] [--population N] [--seed N]
+//                     [--out PATH] [--checkpoint-every N] [--checkpoint-dir DIR]
+//                     [--resume PATH | --from BRAIN]
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use flappy_rust::brain_file::{load_brain, save_brain};
+use flappy_rust::checkpoint::Checkpoint;
+use flappy_rust::config::Config;
+use flappy_rust::resources::*;
+use flappy_rust::setup::headless_app;
+
+const USAGE: &str = "Usage: flappy-train [--config FILE] [--generations N] [--population N] [--seed N]
+                   [--out PATH] [--checkpoint-every N] [--checkpoint-dir DIR]
+                   [--resume PATH | --from BRAIN]";
+
+struct TrainArgs {
+    config: Option<PathBuf>,
+    generations: u32,
+    population: Option<usize>,
+    seed: Option<u64>,
+    out: PathBuf,
+    checkpoint_every: Option<u32>,
+    checkpoint_dir: PathBuf,
+    resume: Option<PathBuf>,
+    from: Option<PathBuf>,
+}
+
+impl Default for TrainArgs {
+    fn default() -> Self {
+        Self {
+            config: None,
+            generations: 100,
+            population: None,
+            seed: None,
+            out: PathBuf::from("best_brain.json"),
+            checkpoint_every: None,
+            checkpoint_dir: CheckpointSettings::default().dir,
+            resume: None,
+            from: None,
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<TrainArgs, String> {
+    let mut parsed = TrainArgs::default();
+
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+        let bad_value = |_| format!("Invalid value for {}: {}", flag, value);
+
+        match flag.as_str() {
+            "--config" => parsed.config = Some(PathBuf::from(value)),
+            "--generations" => {
+                let generations: u32 = value.parse().map_err(bad_value)?;
+                if generations == 0 {
+                    return Err(format!("Invalid value for {}: {}", flag, value));
+                }
+                parsed.generations = generations;
+            }
+            "--population" => parsed.population = Some(value.parse().map_err(bad_value)?),
+            "--seed" => parsed.seed = Some(value.parse().map_err(bad_value)?),
+            "--out" => parsed.out = PathBuf::from(value),
+            "--checkpoint-every" => {
+                let every: u32 = value.parse().map_err(bad_value)?;
+                if every == 0 {
+                    return Err(format!("Invalid value for {}: {}", flag, value));
+                }
+                parsed.checkpoint_every = Some(every);
+            }
+            "--checkpoint-dir" => parsed.checkpoint_dir = PathBuf::from(value),
+            "--resume" => parsed.resume = Some(PathBuf::from(value)),
+            "--from" => parsed.from = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown argument: {}", flag)),
+        }
+    }
+
+    if parsed.resume.is_some() && parsed.from.is_some() {
+        return Err("Only one of --resume and --from can be used".to_string());
+    }
+    // The checkpoint brings its own config and RNG, these would go unused
+    if parsed.resume.is_some() {
+        let overridden = [
+            ("--config", parsed.config.is_some()),
+            ("--population", parsed.population.is_some()),
+            ("--seed", parsed.seed.is_some()),
+        ];
+        if let Some((flag, _)) = overridden.iter().find(|(_, given)| *given) {
+            return Err(format!("{} can't be used with --resume, the checkpoint's is kept", flag));
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut sim_state = SimulationState::default();
+    let (config, rng, resume) = match &args.resume {
+        // A resumed run keeps the knobs and randomness it was started with,
+        // flappy.toml isn't even read then
+        Some(path) => match Checkpoint::load(path) {
+            Ok(checkpoint) => {
+                checkpoint.restore_sim_state(&mut sim_state);
+                (checkpoint.config.clone(), checkpoint.rng.clone(), Some(checkpoint))
+            }
+            Err(err) => {
+                eprintln!("Failed to load checkpoint {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let mut config = match Config::load_or_default(args.config.as_deref()) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Failed to load config: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            if let Some(population) = args.population {
+                config.training.population = population;
+            }
+            if let Err(err) = config.validate() {
+                eprintln!("Invalid config: {}", err);
+                std::process::exit(2);
+            }
+
+            let rng = SimRng::new(args.seed.or(config.seed).unwrap_or(0));
+            (config, rng, None)
+        }
+    };
+
+    let from = args.from.as_ref().map(|path| match load_brain(path) {
+        Ok(brain) => brain,
+        Err(err) => {
+            eprintln!("Failed to load brain {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    });
+
+    let population = config.training.population;
+    sim_state.birds_alive = population;
+    let first_generation = sim_state.generation;
+    let last_generation = first_generation.saturating_add(args.generations - 1);
+
+    println!(
+        "Training generations {} to {} of {} birds (seed {})",
+        first_generation, last_generation, population, rng.seed
+    );
+
+    let checkpoints = CheckpointSettings {
+        dir: args.checkpoint_dir.clone(),
+        every: args.checkpoint_every,
+        // Don't write the checkpoint we just resumed from again
+        last_saved_generation: if resume.is_some() { first_generation } else { 0 },
+        ..default()
+    };
+    let mut app = headless_app(config, sim_state, rng, checkpoints, resume, from);
+
+    loop {
+        let sim_state = app.world().resource::<SimulationState>();
+        if sim_state.generation - first_generation >= args.generations || sim_state.solved.is_some() {
+            break;
+        }
+        app.update();
+    }
+
+    let sim_state = app.world().resource::<SimulationState>();
+    let Some(best_brain) = &sim_state.best_brain else {
+        eprintln!("No generation finished, nothing to save");
+        std::process::exit(1);
+    };
+
+    if let Err(err) = save_brain(best_brain, &args.out) {
+        eprintln!("Failed to write {}: {}", args.out.display(), err);
+        std::process::exit(1);
+    }
+
+    if let Some(generation) = sim_state.solved {
+        println!("Solved in generation {}", generation);
+    }
+    println!(
+        "Best fitness {} ({}) saved to {}",
+        sim_state.best_fitness,
+        best_brain,
+        args.out.display()
+    );
+}
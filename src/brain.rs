@@ -0,0 +1,161 @@
+// What flies a bird: a `Net` of fixed shape, or a NEAT `Genome` that grows its
+// own topology as it evolves
+
+use std::fmt;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::TrainingConfig;
+use crate::neat::{Genome, Innovations};
+use crate::nn::{Net, NetError, Scratch};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Brain {
+    Net(Net),
+    Neat(Genome),
+}
+
+// Which kind of brain fresh birds get, see `Config`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BrainKind {
+    #[default]
+    Net,
+    Neat,
+}
+
+impl Brain {
+    // `memory` is the hidden state the brain carries from one prediction to
+    // the next. It starts out blank when it doesn't fit the brain, so clearing
+    // it resets the brain.
+    pub fn predict_into<'s>(
+        &self,
+        inputs: &[f32],
+        memory: &mut Vec<f32>,
+        scratch: &'s mut Scratch,
+    ) -> Result<&'s [f32], NetError> {
+        let size = self.state_size();
+        if memory.len() != size {
+            memory.clear();
+            memory.resize(size, 0.0);
+        }
+
+        match self {
+            Brain::Net(net) => net.predict_into(inputs, memory, scratch),
+            Brain::Neat(genome) => genome.predict_into(inputs, scratch),
+        }
+    }
+
+    // Runs each brain with its memory on its own row of `inputs` (its
+    // `n_inputs` values, rows in the same order as `brains`) and writes the
+    // outputs to `outputs` the same way. With buffers kept from the last call
+    // a whole population is evaluated without allocating.
+    pub fn predict_batch<'a>(
+        brains: impl IntoIterator<Item = (&'a Brain, &'a mut Vec<f32>)>,
+        inputs: &[f32],
+        outputs: &mut Vec<f32>,
+        scratch: &mut Scratch,
+    ) -> Result<(), NetError> {
+        outputs.clear();
+
+        let mut rows = inputs;
+        for (brain, memory) in brains {
+            if rows.len() < brain.n_inputs() {
+                return Err(NetError::WrongInputSize {
+                    expected: brain.n_inputs(),
+                    found: rows.len(),
+                });
+            }
+            let (row, rest) = rows.split_at(brain.n_inputs());
+            outputs.extend_from_slice(brain.predict_into(row, memory, scratch)?);
+            rows = rest;
+        }
+
+        Ok(())
+    }
+
+    // Variation for a copy of this brain outside of the generation step. A
+    // genome may also grow, so it needs the population's innovations.
+    pub fn mutate(
+        &mut self,
+        training: &TrainingConfig,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) {
+        match self {
+            Brain::Net(net) => net.mutate(&training.mutation, rng),
+            Brain::Neat(genome) => genome.mutate(&training.neat, &training.mutation, innovations, rng),
+        }
+    }
+
+    pub fn kind(&self) -> BrainKind {
+        match self {
+            Brain::Net(_) => BrainKind::Net,
+            Brain::Neat(_) => BrainKind::Neat,
+        }
+    }
+
+    pub fn n_inputs(&self) -> usize {
+        match self {
+            Brain::Net(net) => net.n_inputs(),
+            Brain::Neat(genome) => genome.n_inputs(),
+        }
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        match self {
+            Brain::Net(net) => net.n_outputs(),
+            Brain::Neat(genome) => genome.n_outputs(),
+        }
+    }
+
+    // Values of memory the brain needs, 0 for feed-forward ones
+    pub fn state_size(&self) -> usize {
+        match self {
+            Brain::Net(net) => net.state_size(),
+            Brain::Neat(_) => 0,
+        }
+    }
+
+    // See `MutationKind::SelfAdaptive`
+    pub fn step_size(&self) -> Option<f32> {
+        match self {
+            Brain::Net(net) => net.step_size(),
+            Brain::Neat(genome) => genome.step_size(),
+        }
+    }
+
+    pub fn check_shape(&self) -> Result<(), String> {
+        match self {
+            Brain::Net(net) => net.check_shape(),
+            Brain::Neat(genome) => genome.check_shape(),
+        }
+    }
+}
+
+impl fmt::Display for Brain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Brain::Net(net) => {
+                write!(f, "Net {:?}", net.layer_sizes())?;
+                if net.state_size() > 0 {
+                    let kinds: Vec<String> =
+                        net.kinds().iter().map(|k| format!("{:?}", k).to_lowercase()).collect();
+                    write!(f, " {}", kinds.join("/"))?;
+                }
+            }
+            Brain::Neat(genome) => write!(
+                f,
+                "NEAT, {} hidden nodes, {} connections",
+                genome.hidden_count(),
+                genome.enabled_connections()
+            )?,
+        }
+        match self.step_size() {
+            Some(step_size) => write!(f, ", step size {:.3}", step_size),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// Saving and loading brains. Both formats carry a version so old files can be
+// recognised once the network layout changes:
+// - JSON (`.json`): {"format": "flappy-brain", "version": 1, "brain": {...}}
+// - Binary (any other extension): b"FLPB", the version as a little endian u16,
+//   then the brain encoded with bincode
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::brain::Brain;
+use crate::world::NUM_OBSERVATIONS;
+
+pub const BRAIN_FORMAT_VERSION: u16 = 1;
+
+const JSON_FORMAT_NAME: &str = "flappy-brain";
+const BINARY_MAGIC: &[u8; 4] = b"FLPB";
+const BINARY_HEADER_LEN: usize = BINARY_MAGIC.len() + 2;
+
+#[derive(Debug)]
+pub enum BrainFileError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    NotABrainFile,
+    UnsupportedVersion(u16),
+    Malformed(String),
+    // The net was trained on a different set of inputs than the game provides
+    InputMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for BrainFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BrainFileError::Io(err) => write!(f, "{}", err),
+            BrainFileError::Json(err) => write!(f, "Invalid brain JSON: {}", err),
+            BrainFileError::Binary(err) => write!(f, "Invalid brain data: {}", err),
+            BrainFileError::NotABrainFile => write!(f, "Not a brain file"),
+            BrainFileError::UnsupportedVersion(version) => write!(
+                f,
+                "Brain file version {} is not supported (expected {})",
+                version, BRAIN_FORMAT_VERSION
+            ),
+            BrainFileError::Malformed(reason) => write!(f, "Malformed brain: {}", reason),
+            BrainFileError::InputMismatch { expected, found } => write!(
+                f,
+                "Brain takes {} inputs but birds provide {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BrainFileError {}
+
+impl From<io::Error> for BrainFileError {
+    fn from(err: io::Error) -> Self {
+        BrainFileError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for BrainFileError {
+    fn from(err: serde_json::Error) -> Self {
+        BrainFileError::Json(err)
+    }
+}
+
+impl From<bincode::Error> for BrainFileError {
+    fn from(err: bincode::Error) -> Self {
+        BrainFileError::Binary(err)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonBrain<'a> {
+    format: &'a str,
+    version: u16,
+    brain: &'a Brain,
+}
+
+pub fn to_json(brain: &Brain) -> String {
+    let file = JsonBrain {
+        format: JSON_FORMAT_NAME,
+        version: BRAIN_FORMAT_VERSION,
+        brain,
+    };
+    serde_json::to_string_pretty(&file).expect("Brain is always serializable")
+}
+
+pub fn from_json(json: &str) -> Result<Brain, BrainFileError> {
+    // Look at the header first, the brain itself may not parse in other versions
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    if value.get("format").and_then(|f| f.as_str()) != Some(JSON_FORMAT_NAME) {
+        return Err(BrainFileError::NotABrainFile);
+    }
+
+    let version = value
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .ok_or(BrainFileError::NotABrainFile)?;
+    if version != BRAIN_FORMAT_VERSION as u64 {
+        return Err(BrainFileError::UnsupportedVersion(version.min(u16::MAX as u64) as u16));
+    }
+
+    let data = value.get("brain").cloned().ok_or(BrainFileError::NotABrainFile)?;
+    Ok(serde_json::from_value(data)?)
+}
+
+pub fn to_bytes(brain: &Brain) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(BINARY_MAGIC);
+    bytes.extend_from_slice(&BRAIN_FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, brain).expect("Brain is always serializable");
+    bytes
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Brain, BrainFileError> {
+    if bytes.len() < BINARY_HEADER_LEN || &bytes[..BINARY_MAGIC.len()] != BINARY_MAGIC {
+        return Err(BrainFileError::NotABrainFile);
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != BRAIN_FORMAT_VERSION {
+        return Err(BrainFileError::UnsupportedVersion(version));
+    }
+
+    Ok(bincode::deserialize(&bytes[BINARY_HEADER_LEN..])?)
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// Writes JSON for `.json` paths and the binary format otherwise
+pub fn save_brain(brain: &Brain, path: &Path) -> Result<(), BrainFileError> {
+    if is_json(path) {
+        fs::write(path, to_json(brain))?;
+    } else {
+        fs::write(path, to_bytes(brain))?;
+    }
+
+    Ok(())
+}
+
+// Reads a brain and makes sure birds can actually fly with it
+pub fn load_brain(path: &Path) -> Result<Brain, BrainFileError> {
+    let brain = if is_json(path) {
+        from_json(&fs::read_to_string(path)?)?
+    } else {
+        from_bytes(&fs::read(path)?)?
+    };
+
+    brain.check_shape().map_err(BrainFileError::Malformed)?;
+
+    if brain.n_inputs() != NUM_OBSERVATIONS {
+        return Err(BrainFileError::InputMismatch {
+            expected: NUM_OBSERVATIONS,
+            found: brain.n_inputs(),
+        });
+    }
+    if brain.n_outputs() != 1 {
+        return Err(BrainFileError::Malformed(format!(
+            "Brain has {} outputs, birds need exactly 1 (flap or not)",
+            brain.n_outputs()
+        )));
+    }
+
+    Ok(brain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::neat::{Genome, Innovations, NeatParams};
+    use crate::nn::{Activation, Initialization, LayerKind, Net};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flappy-brain-{}-{}", std::process::id(), name))
+    }
+
+    fn net(layer_sizes: Vec<usize>, kinds: Vec<LayerKind>, rng: &mut ChaCha8Rng) -> Brain {
+        let activations = vec![Activation::Tanh; kinds.len()];
+        Brain::Net(Net::new(layer_sizes, activations, kinds, &Initialization::default(), rng).unwrap())
+    }
+
+    fn brains() -> Vec<Brain> {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let neat = Genome::new(NUM_OBSERVATIONS, 1, &NeatParams::default(), &mut Innovations::default(), &mut rng);
+        vec![
+            net(vec![NUM_OBSERVATIONS, 8, 1], vec![LayerKind::Dense; 2], &mut rng),
+            net(vec![NUM_OBSERVATIONS, 4, 1], vec![LayerKind::Gated, LayerKind::Recurrent], &mut rng),
+            Brain::Neat(neat),
+        ]
+    }
+
+    #[test]
+    fn brains_round_trip_through_both_formats() {
+        for (i, brain) in brains().iter().enumerate() {
+            for name in [format!("{}.json", i), format!("{}.brain", i)] {
+                let path = temp_path(&name);
+                save_brain(brain, &path).unwrap();
+                let loaded = load_brain(&path).unwrap();
+                std::fs::remove_file(&path).unwrap();
+
+                assert_eq!(to_bytes(&loaded), to_bytes(brain), "{} as {}", brain, name);
+            }
+        }
+
+        let path = temp_path("header.json");
+        save_brain(&brains()[0], &path).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(json["format"], JSON_FORMAT_NAME);
+        assert_eq!(json["version"], BRAIN_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn unknown_versions_and_other_files_are_rejected() {
+        let brain = &brains()[0];
+
+        let newer = to_json(brain).replacen(
+            &format!("\"version\": {}", BRAIN_FORMAT_VERSION),
+            "\"version\": 99",
+            1,
+        );
+        assert!(matches!(from_json(&newer), Err(BrainFileError::UnsupportedVersion(99))));
+        assert!(matches!(from_json("{\"format\": \"leaderboard\"}"), Err(BrainFileError::NotABrainFile)));
+        assert!(matches!(from_json("not json"), Err(BrainFileError::Json(_))));
+
+        let mut newer = to_bytes(brain);
+        newer[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert!(matches!(from_bytes(&newer), Err(BrainFileError::UnsupportedVersion(99))));
+        assert!(matches!(from_bytes(b"FLPC\x01\x00"), Err(BrainFileError::NotABrainFile)));
+        assert!(matches!(from_bytes(b"FLP"), Err(BrainFileError::NotABrainFile)));
+    }
+
+    #[test]
+    fn brains_birds_cant_fly_with_are_rejected() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let path = temp_path("mismatch.json");
+
+        save_brain(&net(vec![3, 4, 1], vec![LayerKind::Dense; 2], &mut rng), &path).unwrap();
+        assert!(matches!(
+            load_brain(&path),
+            Err(BrainFileError::InputMismatch { expected: NUM_OBSERVATIONS, found: 3 })
+        ));
+
+        save_brain(&net(vec![NUM_OBSERVATIONS, 4, 2], vec![LayerKind::Dense; 2], &mut rng), &path).unwrap();
+        assert!(matches!(load_brain(&path), Err(BrainFileError::Malformed(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+// Training checkpoints: everything needed to stop an AI run and later carry on
+// exactly where it left off, down to the RNG states and the position of every
+// bird. Stored as b"FLPC", the version as a little endian u16, then bincode.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::brain::Brain;
+use crate::neat::Neat;
+use crate::resources::{SimRng, SimulationState};
+use crate::world::{FlappyWorld, NUM_OBSERVATIONS};
+
+pub const CHECKPOINT_FORMAT_VERSION: u16 = 1;
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"FLPC";
+const CHECKPOINT_HEADER_LEN: usize = CHECKPOINT_MAGIC.len() + 2;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Binary(bincode::Error),
+    NotACheckpoint,
+    UnsupportedVersion(u16),
+    Malformed(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::Binary(err) => write!(f, "Invalid checkpoint data: {}", err),
+            CheckpointError::NotACheckpoint => write!(f, "Not a checkpoint file"),
+            CheckpointError::UnsupportedVersion(version) => write!(
+                f,
+                "Checkpoint version {} is not supported (expected {})",
+                version, CHECKPOINT_FORMAT_VERSION
+            ),
+            CheckpointError::Malformed(reason) => write!(f, "Malformed checkpoint: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for CheckpointError {
+    fn from(err: bincode::Error) -> Self {
+        CheckpointError::Binary(err)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub generation: u32,
+    // Brain of bird `i` of the world
+    pub population: Vec<Brain>,
+    // Hidden state of bird `i`'s brain
+    pub memories: Vec<Vec<f32>>,
+    pub best_brain: Option<Brain>,
+    pub best_fitness: f32,
+    // Courses of the generation flown so far and what every bird reached on
+    // them, see `SimulationState`
+    pub course: usize,
+    pub course_fitness: Vec<Vec<f32>>,
+    pub generation_score: u32,
+    pub world: FlappyWorld,
+    pub rng: SimRng,
+    // Species and innovations, only used with NEAT brains
+    pub neat: Neat,
+    // Knobs the run was started with, a resumed run keeps using them
+    pub config: Config,
+}
+
+impl Checkpoint {
+    // `brains` yields (bird index, brain, memory) for every bird in the world
+    pub fn capture<'a>(
+        sim_state: &SimulationState,
+        world: &FlappyWorld,
+        rng: &SimRng,
+        neat: &Neat,
+        config: &Config,
+        brains: impl Iterator<Item = (usize, &'a Brain, &'a [f32])>,
+    ) -> Result<Self, CheckpointError> {
+        let mut population: Vec<Option<Brain>> = vec![None; world.birds().len()];
+        let mut memories = vec![Vec::new(); world.birds().len()];
+        for (index, brain, memory) in brains {
+            population[index] = Some(brain.clone());
+            memories[index] = memory.to_vec();
+        }
+        let population: Vec<Brain> = population
+            .into_iter()
+            .collect::<Option<_>>()
+            .ok_or_else(|| CheckpointError::Malformed("Every bird needs a brain".to_string()))?;
+
+        Ok(Self {
+            generation: sim_state.generation,
+            population,
+            memories,
+            best_brain: sim_state.best_brain.clone(),
+            best_fitness: sim_state.best_fitness,
+            course: sim_state.course,
+            course_fitness: sim_state.course_fitness.clone(),
+            generation_score: sim_state.generation_score,
+            world: world.clone(),
+            rng: rng.clone(),
+            neat: neat.clone(),
+            config: config.clone(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        bytes.extend_from_slice(&CHECKPOINT_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)?;
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < CHECKPOINT_HEADER_LEN || &bytes[..CHECKPOINT_MAGIC.len()] != CHECKPOINT_MAGIC {
+            return Err(CheckpointError::NotACheckpoint);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != CHECKPOINT_FORMAT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+
+        let checkpoint: Checkpoint = bincode::deserialize(&bytes[CHECKPOINT_HEADER_LEN..])?;
+        checkpoint.config.validate().map_err(CheckpointError::Malformed)?;
+        if checkpoint.population.len() != checkpoint.world.birds().len()
+            || checkpoint.memories.len() != checkpoint.population.len()
+        {
+            return Err(CheckpointError::Malformed(format!(
+                "{} brains and {} memories for {} birds",
+                checkpoint.population.len(),
+                checkpoint.memories.len(),
+                checkpoint.world.birds().len()
+            )));
+        }
+        let flown = |scores: &Vec<f32>| scores.len() == checkpoint.course;
+        if checkpoint.course > 0
+            && (checkpoint.course_fitness.len() != checkpoint.population.len()
+                || !checkpoint.course_fitness.iter().all(flown))
+        {
+            return Err(CheckpointError::Malformed(format!(
+                "Fitness of {} courses doesn't match {} birds",
+                checkpoint.course,
+                checkpoint.population.len()
+            )));
+        }
+        for brain in checkpoint.population.iter().chain(checkpoint.best_brain.iter()) {
+            brain.check_shape().map_err(CheckpointError::Malformed)?;
+            if brain.n_inputs() != NUM_OBSERVATIONS || brain.n_outputs() != 1 {
+                return Err(CheckpointError::Malformed(format!(
+                    "{} can't fly a bird, that takes {} inputs and 1 output",
+                    brain, NUM_OBSERVATIONS
+                )));
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    // Puts the counters back where they were when the checkpoint was taken
+    pub fn restore_sim_state(&self, sim_state: &mut SimulationState) {
+        sim_state.generation = self.generation;
+        sim_state.birds_alive = self.world.alive_count();
+        sim_state.best_brain = self.best_brain.clone();
+        sim_state.best_fitness = self.best_fitness;
+        sim_state.course = self.course;
+        sim_state.course_fitness = self.course_fitness.clone();
+        sim_state.generation_score = self.generation_score;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bevy::prelude::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::brain_file::to_bytes;
+    use crate::components::Bird;
+    use crate::nn::{Activation, Initialization, LayerKind, Net};
+    use crate::resources::{CheckpointSettings, GameWorld, NeatState};
+    use crate::setup::headless_app;
+
+    use super::*;
+
+    // Small and quick, with two courses a generation so a checkpoint can be
+    // taken between them
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.training.population = 20;
+        config.training.elitism = 2;
+        config.training.evaluation.courses = 2;
+        config.training.evaluation.max_ticks = 300;
+        config
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flappy-checkpoint-{}-{}", std::process::id(), name))
+    }
+
+    fn fresh_app(config: &Config) -> App {
+        let sim_state = SimulationState {
+            birds_alive: config.training.population,
+            ..Default::default()
+        };
+        headless_app(config.clone(), sim_state, SimRng::new(7), CheckpointSettings::default(), None, None)
+    }
+
+    fn resumed_app(checkpoint: Checkpoint) -> App {
+        let mut sim_state = SimulationState::default();
+        checkpoint.restore_sim_state(&mut sim_state);
+        let (config, rng) = (checkpoint.config.clone(), checkpoint.rng.clone());
+        headless_app(config, sim_state, rng, CheckpointSettings::default(), Some(checkpoint), None)
+    }
+
+    fn generation(app: &App) -> u32 {
+        app.world().resource::<SimulationState>().generation
+    }
+
+    fn run_until_generation(app: &mut App, generation: u32) {
+        while self::generation(app) < generation {
+            app.update();
+        }
+    }
+
+    fn capture(app: &mut App) -> Checkpoint {
+        let mut birds = app.world_mut().query::<&Bird>();
+        let world = app.world();
+        let brains = birds
+            .iter(world)
+            .filter_map(|bird| bird.brain.as_ref().map(|brain| (bird.index, brain, bird.memory.as_slice())));
+        Checkpoint::capture(
+            world.resource::<SimulationState>(),
+            &world.resource::<GameWorld>().0,
+            world.resource::<SimRng>(),
+            &world.resource::<NeatState>().0,
+            world.resource::<Config>(),
+            brains,
+        )
+        .unwrap()
+    }
+
+    // Every bird's brain in bird order, encoded so they can be compared
+    fn brains(app: &mut App) -> Vec<Vec<u8>> {
+        let mut birds = app.world_mut().query::<&Bird>();
+        let mut brains: Vec<_> = birds
+            .iter(app.world())
+            .map(|bird| (bird.index, to_bytes(bird.brain.as_ref().unwrap())))
+            .collect();
+        brains.sort_by_key(|(index, _)| *index);
+        brains.into_iter().map(|(_, brain)| brain).collect()
+    }
+
+    #[test]
+    fn a_resumed_run_carries_on_exactly_like_an_uninterrupted_one() {
+        let path = temp_path("resume");
+        let mut uninterrupted = fresh_app(&config());
+
+        // Part way through the first course of generation 3
+        run_until_generation(&mut uninterrupted, 3);
+        for _ in 0..40 {
+            uninterrupted.update();
+        }
+        capture(&mut uninterrupted).save(&path).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.generation, 3);
+        let mut resumed = resumed_app(checkpoint);
+
+        run_until_generation(&mut uninterrupted, 6);
+        run_until_generation(&mut resumed, 6);
+        assert_eq!(brains(&mut uninterrupted), brains(&mut resumed));
+
+        let (a, b) = (
+            uninterrupted.world().resource::<SimulationState>(),
+            resumed.world().resource::<SimulationState>(),
+        );
+        assert_eq!(a.best_fitness, b.best_fitness);
+        assert_eq!(a.best_brain.as_ref().map(to_bytes), b.best_brain.as_ref().map(to_bytes));
+        assert!(a.best_fitness > 0.0);
+    }
+
+    #[test]
+    fn load_rejects_other_files_and_versions() {
+        let path = temp_path("header");
+        let mut app = fresh_app(&config());
+        app.update();
+        capture(&mut app).save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, b"FLPB\x01\x00 not a checkpoint").unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::NotACheckpoint)));
+        std::fs::write(&path, b"FLP").unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::NotACheckpoint)));
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&99u16.to_le_bytes());
+        std::fs::write(&path, newer).unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::UnsupportedVersion(99))));
+
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::Binary(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_brains_that_dont_fit_the_birds() {
+        let path = temp_path("population");
+        let mut app = fresh_app(&config());
+        app.update();
+        let checkpoint = capture(&mut app);
+
+        let mut missing_bird = checkpoint.clone();
+        missing_bird.population.pop();
+        missing_bird.save(&path).unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::Malformed(_))));
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let three_inputs = Net::new(
+            vec![3, 4, 1],
+            vec![Activation::Sigmoid; 2],
+            vec![LayerKind::Dense; 2],
+            &Initialization::default(),
+            &mut rng,
+        )
+        .unwrap();
+        let mut wrong_shape = checkpoint;
+        wrong_shape.population[4] = Brain::Net(three_inputs);
+        wrong_shape.save(&path).unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::Malformed(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -21,27 +21,37 @@ pub struct ScoreText;
 #[derive(Component)]
 pub struct HighScoreText;
 
+// Sprite for bird `index` of the GameWorld
 #[derive(Component)]
 pub struct Bird{
   pub timer: Timer,
-  pub velocity: f32,
-  pub brain: Option<crate::nn::Net>,
-  pub is_dead: bool,
-  pub fitness: f32,
+  pub index: usize,
+  pub brain: Option<crate::brain::Brain>,
+  // Hidden state of a recurrent brain, cleared whenever the bird starts over
+  pub memory: Vec<f32>,
+  // Flap on the next simulation tick
+  pub flap: bool,
 }
 
 
+// Sprites for pipe pair `pair` of the GameWorld
 #[derive(Component)]
 pub struct UpperPipe{
-  pub passed:bool,
+  pub pair: usize,
 }
  
 #[derive(Component)]
-pub struct LowerPipe;
+pub struct LowerPipe{
+  pub pair: usize,
+}
 
 #[derive(Component)]
 pub struct GenUi;
 
+// Top scores, shown under the game over sign
+#[derive(Component)]
+pub struct LeaderboardText;
+
 // #[derive(Component)]
 // pub struct PressSpaceBarTextt(pub Timer);
 
@@ -0,0 +1,402 @@
+// Gameplay and training knobs, read from a TOML file at startup so experiments
+// don't need a rebuild. Everything is optional, left out values keep the
+// defaults below:
+//
+//   seed = 42
+//
+//   [world]
+//   gravity = 1470.0
+//   flap_velocity = 300.0
+//   pipe_spacing = 200.0
+//   pipe_gap_offset = 450.0
+//
+//   [training]
+//   population = 1000
+//   elitism = 4
+//   layer_sizes = [5, 8, 1]
+//   # sigmoid, tanh, relu, leaky_relu, linear or step
+//   activations = ["sigmoid", "sigmoid"]
+//   # dense, recurrent or gated, one per layer after the inputs, all dense
+//   # when empty
+//   layer_kinds = []
+//   # none, uniform, neuron, single_point or blend
+//   crossover = "uniform"
+//
+//   # net (layer_sizes and activations below) or neat (grows its own topology)
+//   brain = "net"
+//
+//   [training.init]
+//   # uniform (within -1..1), xavier or he
+//   weights = "uniform"
+//   zero_bias = false
+//
+//   [training.fitness]
+//   # Reward per tick alive, per pipe cleared and per tick in line with the gap
+//   # of the next pipe, minus the cost of every flap and of dying
+//   survival = 1.0
+//   pipes = 20.0
+//   gap_alignment = 1.0
+//   flap_cost = 0.0
+//   death_penalty = 0.0
+//
+//   [training.evaluation]
+//   # Courses every brain flies per generation, and how the fitness of all
+//   # of them is combined: mean, min or percentile
+//   courses = 1
+//   aggregate = "mean"
+//   # percentile only, 0 is the worst course and 1 the best
+//   percentile = 0.25
+//   # End a course early, even with birds still flying
+//   max_ticks = 36000
+//   max_pipes = 1000
+//
+//   # flappy-train stops once the champion of a generation passes this many
+//   # pipes on each of `courses` held-out courses within `max_ticks` (never
+//   # when left out)
+//   [training.solved]
+//   # pipes = 500
+//   courses = 5
+//   max_ticks = 100000
+//   seed = 1592590336
+//
+//   [training.selection]
+//   # fitness_proportional, tournament, rank or truncation, picks the parents
+//   # of net brains (NEAT breeds within its species)
+//   kind = "fitness_proportional"
+//   tournament_size = 3
+//   truncation = 0.5
+//
+//   [training.mutation]
+//   # uniform, gaussian or self_adaptive
+//   kind = "uniform"
+//   rate = 0.1
+//   variation = 0.6
+//   # self_adaptive only
+//   learning_rate = 0.2
+//   min_step_size = 0.01
+//   # none, linear or exponential, not with self_adaptive
+//   anneal = "none"
+//   anneal_generations = 500
+//   anneal_min = 0.1
+//
+//   [training.neat]
+//   compatibility_threshold = 3.0
+//   excess_coefficient = 1.0
+//   disjoint_coefficient = 1.0
+//   weight_coefficient = 0.4
+//   add_connection_rate = 0.05
+//   add_node_rate = 0.03
+//   crossover_rate = 0.75
+//   survival_threshold = 0.2
+//   hidden_activation = "sigmoid"
+//   output_activation = "sigmoid"
+//
+//   # flappy-imitate, training a net on recorded Human runs
+//   [imitation]
+//   balance = true
+//
+//   [imitation.backprop]
+//   # mean_squared or cross_entropy
+//   loss = "cross_entropy"
+//   # sgd, momentum or adam
+//   optimizer = "adam"
+//   learning_rate = 0.001
+//   epochs = 20
+//   batch_size = 32
+//   momentum = 0.9
+//   second_momentum = 0.999
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::Resource;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::brain::{Brain, BrainKind};
+use crate::constants::NUM_BIRDS;
+use crate::fitness::{EvaluationParams, FitnessWeights, SolvedParams};
+use crate::imitation::ImitationConfig;
+use crate::neat::{Genome, Innovations, NeatParams};
+use crate::nn::{Activation, Crossover, Initialization, LayerKind, MutationParams, Net};
+use crate::selection::SelectionParams;
+use crate::world::{WorldParams, NUM_OBSERVATIONS};
+
+// Read from the working directory when no --config is given, if it exists
+pub const DEFAULT_CONFIG_PATH: &str = "flappy.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Toml(err) => write!(f, "Invalid config TOML: {}", err),
+            ConfigError::Invalid(reason) => write!(f, "Invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Toml(err)
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Seed for the whole run, a random one when not set
+    pub seed: Option<u64>,
+    pub world: WorldParams,
+    pub training: TrainingConfig,
+    pub imitation: ImitationConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+    // Birds per generation
+    pub population: usize,
+    // Best brains carried over unchanged into the next generation (NEAT keeps
+    // the best of each species instead)
+    pub elitism: usize,
+    pub brain: BrainKind,
+    // Net::new layer sizes for fresh brains, inputs first
+    pub layer_sizes: Vec<usize>,
+    // One per layer after the inputs
+    pub activations: Vec<Activation>,
+    // One per layer after the inputs too, or none for all dense
+    pub layer_kinds: Vec<LayerKind>,
+    // Starting weights of fresh nets
+    pub init: Initialization,
+    // What birds are rewarded for
+    pub fitness: FitnessWeights,
+    // How many courses a generation flies and what counts of them
+    pub evaluation: EvaluationParams,
+    // When training is done
+    pub solved: SolvedParams,
+    // How the parents of every child are picked
+    pub selection: SelectionParams,
+    // How two parents are combined into a child before it's mutated
+    pub crossover: Crossover,
+    pub mutation: MutationParams,
+    pub neat: NeatParams,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            population: NUM_BIRDS,
+            elitism: 4,
+            brain: BrainKind::Net,
+            layer_sizes: vec![NUM_OBSERVATIONS, 8, 1],
+            activations: vec![Activation::Sigmoid, Activation::Sigmoid],
+            layer_kinds: Vec::new(),
+            init: Initialization::default(),
+            fitness: FitnessWeights::default(),
+            evaluation: EvaluationParams::default(),
+            solved: SolvedParams::default(),
+            selection: SelectionParams::default(),
+            crossover: Crossover::Uniform,
+            mutation: MutationParams::default(),
+            neat: NeatParams::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(toml)?;
+        config.validate().map_err(ConfigError::Invalid)?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    // Loads `path` if given, otherwise DEFAULT_CONFIG_PATH if there is one,
+    // otherwise the defaults
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::load(Path::new(DEFAULT_CONFIG_PATH))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.world.validate().map_err(|e| format!("world.{}", e))?;
+        self.training.validate().map_err(|e| format!("training.{}", e))?;
+        self.imitation.validate().map_err(|e| format!("imitation.{}", e))
+    }
+}
+
+impl TrainingConfig {
+    // A fresh, randomly initialised brain of the configured kind and shape
+    pub fn new_brain(&self, innovations: &mut Innovations, rng: &mut impl Rng) -> Brain {
+        match self.brain {
+            BrainKind::Net => Brain::Net(Net::new(
+                self.layer_sizes.clone(),
+                self.activations.clone(),
+                self.layer_kinds(),
+                &self.init,
+                rng,
+            )
+            .expect("TrainingConfig::validate checks the layers")),
+            BrainKind::Neat => {
+                Brain::Neat(Genome::new(NUM_OBSERVATIONS, 1, &self.neat, innovations, rng))
+            }
+        }
+    }
+
+    // Brains for `population` birds: `seed` as is for the first and mutated
+    // copies of it for the rest, fresh ones without a seed
+    pub fn new_population(
+        &self,
+        population: usize,
+        seed: Option<&Brain>,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) -> Vec<Brain> {
+        // A genome from elsewhere may use innovation numbers this run hasn't seen
+        if let Some(Brain::Neat(genome)) = seed {
+            innovations.record(genome);
+        }
+
+        (0..population)
+            .map(|index| match seed {
+                Some(seed) if index == 0 => seed.clone(),
+                Some(seed) => {
+                    let mut child_brain = seed.clone();
+                    child_brain.mutate(self, innovations, rng);
+                    child_brain
+                }
+                None => self.new_brain(innovations, rng),
+            })
+            .collect()
+    }
+
+    // `layer_kinds` with the empty default spelled out
+    pub fn layer_kinds(&self) -> Vec<LayerKind> {
+        if self.layer_kinds.is_empty() {
+            vec![LayerKind::Dense; self.activations.len()]
+        } else {
+            self.layer_kinds.clone()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.population == 0 {
+            return Err("population must be at least 1".to_string());
+        }
+        if self.elitism >= self.population {
+            return Err(format!(
+                "elitism ({}) must be less than the population ({})",
+                self.elitism, self.population
+            ));
+        }
+
+        let sizes = &self.layer_sizes;
+        if sizes.len() < 2 || sizes.contains(&0) {
+            return Err(format!(
+                "layer_sizes needs at least 2 non-empty layers, got {:?}",
+                sizes
+            ));
+        }
+        if sizes[0] != NUM_OBSERVATIONS || sizes[sizes.len() - 1] != 1 {
+            return Err(format!(
+                "layer_sizes must start with {} inputs and end with 1 output, got {:?}",
+                NUM_OBSERVATIONS, sizes
+            ));
+        }
+
+        if self.activations.len() != sizes.len() - 1 {
+            return Err(format!(
+                "activations needs one entry per layer after the inputs ({}), got {}",
+                sizes.len() - 1,
+                self.activations.len()
+            ));
+        }
+        if !self.layer_kinds.is_empty() && self.layer_kinds.len() != sizes.len() - 1 {
+            return Err(format!(
+                "layer_kinds needs one entry per layer after the inputs ({}), got {}",
+                sizes.len() - 1,
+                self.layer_kinds.len()
+            ));
+        }
+        // Anything else new_brain would trip over
+        Net::check_layout(sizes, &self.activations, &self.layer_kinds()).map_err(|e| e.to_string())?;
+
+        self.fitness.validate().map_err(|e| format!("fitness.{}", e))?;
+        self.evaluation.validate().map_err(|e| format!("evaluation.{}", e))?;
+        self.solved.validate().map_err(|e| format!("solved.{}", e))?;
+        self.selection.validate().map_err(|e| format!("selection.{}", e))?;
+        self.mutation.validate().map_err(|e| format!("mutation.{}", e))?;
+        self.neat.validate().map_err(|e| format!("neat.{}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Config has no PartialEq, JSON tells two apart just as well
+    fn json(config: &Config) -> String {
+        serde_json::to_string(config).unwrap()
+    }
+
+    fn invalid(toml: &str) -> String {
+        match Config::from_toml(toml) {
+            Err(ConfigError::Invalid(reason)) => reason,
+            other => panic!("{:?} was not rejected as invalid: {:?}", toml, other.map(|c| json(&c))),
+        }
+    }
+
+    #[test]
+    fn an_empty_file_is_the_default_config() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(json(&config), json(&Config::default()));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for toml in ["sead = 1", "[world]\ngravity = 1.0\nwind = 2.0", "[training.mutation]\nrates = 0.2"] {
+            assert!(matches!(Config::from_toml(toml), Err(ConfigError::Toml(_))), "{:?}", toml);
+        }
+    }
+
+    #[test]
+    fn inconsistent_training_settings_are_invalid() {
+        let reason = invalid("[training]\npopulation = 10\nelitism = 10");
+        assert!(reason.starts_with("training.elitism"), "{}", reason);
+
+        for layer_sizes in ["[4, 8, 1]", "[5, 8, 2]"] {
+            let reason = invalid(&format!("[training]\nlayer_sizes = {}", layer_sizes));
+            assert!(reason.starts_with("training.layer_sizes"), "{}", reason);
+        }
+
+        let reason = invalid("[training]\nactivations = [\"tanh\"]");
+        assert!(reason.starts_with("training.activations"), "{}", reason);
+        let reason = invalid("[training]\nlayer_kinds = [\"dense\", \"recurrent\", \"dense\"]");
+        assert!(reason.starts_with("training.layer_kinds"), "{}", reason);
+    }
+}
@@ -1,3 +1,6 @@
 pub const WINDOW_WIDTH: f32 = 800.0;
 pub const WINDOW_HEIGHT: f32 = 512.0;
-pub const NUM_BIRDS: usize = 1000;
\ No newline at end of file
+pub const NUM_BIRDS: usize = 1000;
+// The simulation always advances in steps of this size; fast mode runs more
+// steps per frame rather than longer ones
+pub const FIXED_TIMESTEP: f64 = 1.0 / 60.0;
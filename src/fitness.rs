@@ -0,0 +1,393 @@
+// What the birds are rewarded for. The world counts up every term for each bird
+// and `FitnessWeights` turns the counts into fitness, so all of it is tuned
+// from the Config and a bird's fitness can always be taken apart again.
+// `EvaluationParams` then combine the fitness of several courses into one, and
+// `SolvedParams` tell when a brain is good enough to stop training.
+
+use serde::{Deserialize, Serialize};
+
+use crate::brain::Brain;
+use crate::nn::{NetError, Scratch};
+use crate::world::{FlappyWorld, WorldParams};
+
+// How much one unit of every term is worth, see `Config`. The defaults are the
+// rewards birds always got.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FitnessWeights {
+    // Per tick alive
+    pub survival: f32,
+    // Per pipe cleared, every bird alive as a pipe goes past gets it
+    pub pipes: f32,
+    // Per tick right in the middle of the gap of a close pipe, less further off
+    pub gap_alignment: f32,
+    // Taken off per flap
+    pub flap_cost: f32,
+    // Taken off once when the bird dies
+    pub death_penalty: f32,
+}
+
+impl Default for FitnessWeights {
+    fn default() -> Self {
+        Self {
+            survival: 1.0,
+            pipes: 20.0,
+            gap_alignment: 1.0,
+            flap_cost: 0.0,
+            death_penalty: 0.0,
+        }
+    }
+}
+
+impl FitnessWeights {
+    pub fn fitness(&self, terms: &FitnessTerms) -> f32 {
+        self.contributions(terms).iter().map(|(_, value)| value).sum()
+    }
+
+    // Share of the fitness coming from every term, by name
+    pub fn contributions(&self, terms: &FitnessTerms) -> [(&'static str, f32); 5] {
+        [
+            ("survival", self.survival * terms.survival),
+            ("pipes", self.pipes * terms.pipes),
+            ("gap alignment", self.gap_alignment * terms.gap_alignment),
+            ("flaps", -self.flap_cost * terms.flaps),
+            ("death", -self.death_penalty * terms.deaths),
+        ]
+    }
+
+    // The contributions of the weighted terms, for logs and the UI
+    pub fn describe(&self, terms: &FitnessTerms) -> String {
+        let parts: Vec<String> = self
+            .contributions(terms)
+            .iter()
+            .zip(self.weights())
+            .filter(|(_, weight)| *weight != 0.0)
+            .map(|((name, value), _)| format!("{} {:.1}", name, value))
+            .collect();
+        parts.join(", ")
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let names = ["survival", "pipes", "gap_alignment", "flap_cost", "death_penalty"];
+        for (name, weight) in names.iter().zip(self.weights()) {
+            if !weight.is_finite() {
+                return Err(format!("{} must be a number, got {}", name, weight));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn weights(&self) -> [f32; 5] {
+        [self.survival, self.pipes, self.gap_alignment, self.flap_cost, self.death_penalty]
+    }
+}
+
+// How the fitness a brain reached on each course of a generation becomes the
+// one it's selected by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    #[default]
+    Mean,
+    // The worst course, only brains that fly every course well do well
+    Min,
+    // The fitness `percentile` of the courses were worse than, 0 being the
+    // worst course and 1 the best
+    Percentile,
+}
+
+// See `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvaluationParams {
+    // Courses every brain flies per generation, each laid out from its own seed
+    pub courses: usize,
+    pub aggregate: Aggregate,
+    // Percentile only
+    pub percentile: f32,
+    // A course ends once this many ticks went by or pipes were passed even
+    // with birds still alive, they keep the fitness they have by then. Keeps
+    // a generation that flies perfectly from going on forever.
+    pub max_ticks: u64,
+    pub max_pipes: u32,
+}
+
+impl Default for EvaluationParams {
+    fn default() -> Self {
+        Self {
+            courses: 1,
+            aggregate: Aggregate::Mean,
+            percentile: 0.25,
+            // 10 minutes at 60 ticks a second
+            max_ticks: 36_000,
+            max_pipes: 1000,
+        }
+    }
+}
+
+impl EvaluationParams {
+    // Whether the course `world` is on has been flown
+    pub fn course_over(&self, world: &FlappyWorld) -> bool {
+        world.alive_count() == 0
+            || world.ticks() >= self.max_ticks
+            || world.score() >= self.max_pipes
+    }
+
+    // The fitness of a brain that reached `scores` on its courses, 0 for none
+    pub fn aggregate(&self, scores: &[f32]) -> f32 {
+        if scores.is_empty() {
+            return 0.0;
+        }
+
+        match self.aggregate {
+            Aggregate::Mean => scores.iter().sum::<f32>() / scores.len() as f32,
+            Aggregate::Min => scores.iter().copied().fold(f32::INFINITY, f32::min),
+            Aggregate::Percentile => {
+                let mut sorted = scores.to_vec();
+                sorted.sort_by(f32::total_cmp);
+                let rank = (self.percentile * (sorted.len() - 1) as f32).round() as usize;
+                sorted[rank.min(sorted.len() - 1)]
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.courses == 0 {
+            return Err("courses must be at least 1".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.percentile) {
+            return Err(format!("percentile must be within 0..1, got {}", self.percentile));
+        }
+        if self.max_ticks == 0 {
+            return Err("max_ticks must be at least 1".to_string());
+        }
+        if self.max_pipes == 0 {
+            return Err("max_pipes must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// When training is done, see `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SolvedParams {
+    // Pipes the champion of a generation has to pass on every held-out
+    // course, never solved when not set
+    pub pipes: Option<u32>,
+    pub courses: usize,
+    // A held-out course the champion is still flying after this many ticks
+    // counts as failed
+    pub max_ticks: u64,
+    // Held-out course `k` is laid out from `seed + k`, none of them are ever
+    // trained on
+    pub seed: u64,
+}
+
+impl Default for SolvedParams {
+    fn default() -> Self {
+        Self {
+            pipes: None,
+            courses: 5,
+            // Well over the ~80 ticks a pipe takes with the default world,
+            // times 500 pipes
+            max_ticks: 100_000,
+            seed: 0x5eed_0000,
+        }
+    }
+}
+
+impl SolvedParams {
+    // Whether `brain` gets through every held-out course
+    pub fn is_solved(&self, brain: &Brain, params: WorldParams) -> Result<bool, NetError> {
+        let Some(pipes) = self.pipes else {
+            return Ok(false);
+        };
+
+        for course in 0..self.courses {
+            let seed = self.seed.wrapping_add(course as u64);
+            if pipes_passed(brain, params, seed, pipes, self.max_ticks)? < pipes {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pipes == Some(0) {
+            return Err("pipes must be at least 1".to_string());
+        }
+        if self.courses == 0 {
+            return Err("courses must be at least 1".to_string());
+        }
+        if self.max_ticks == 0 {
+            return Err("max_ticks must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Pipes `brain` passes on the course laid out from `seed` before it dies or
+// `max_ticks` go by, but no more than `limit`
+pub fn pipes_passed(
+    brain: &Brain,
+    params: WorldParams,
+    seed: u64,
+    limit: u32,
+    max_ticks: u64,
+) -> Result<u32, NetError> {
+    let mut world = FlappyWorld::new(1, seed, params);
+    let mut memory = Vec::new();
+    let mut scratch = Scratch::default();
+    let mut observations = world.observations();
+    let mut rewards = Vec::new();
+    while world.alive_count() > 0 && world.score() < limit && world.ticks() < max_ticks {
+        // Flaps just like in `bird_brain_system`
+        let flap = brain.predict_into(&observations[0], &mut memory, &mut scratch)?[0] > 0.5;
+        world.step_into(&[flap], &mut observations, &mut rewards).expect("One bird");
+    }
+
+    Ok(world.score())
+}
+
+// Unweighted amounts a bird collected since the last reset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FitnessTerms {
+    // Ticks
+    pub survival: f32,
+    pub pipes: f32,
+    pub gap_alignment: f32,
+    pub flaps: f32,
+    // 1 once the bird is dead
+    pub deaths: f32,
+}
+
+impl FitnessTerms {
+    pub fn add(&mut self, other: &FitnessTerms) {
+        self.survival += other.survival;
+        self.pipes += other.pipes;
+        self.gap_alignment += other.gap_alignment;
+        self.flaps += other.flaps;
+        self.deaths += other.deaths;
+    }
+
+    // Zero for no terms at all
+    pub fn average<'a>(terms: impl IntoIterator<Item = &'a FitnessTerms>) -> FitnessTerms {
+        let mut sum = FitnessTerms::default();
+        let mut count = 0;
+        for terms in terms {
+            sum.add(terms);
+            count += 1;
+        }
+
+        let scale = 1.0 / count.max(1) as f32;
+        FitnessTerms {
+            survival: sum.survival * scale,
+            pipes: sum.pipes * scale,
+            gap_alignment: sum.gap_alignment * scale,
+            flaps: sum.flaps * scale,
+            deaths: sum.deaths * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::nn::{Activation, Initialization, LayerKind, Net};
+    use crate::world::NUM_OBSERVATIONS;
+
+    use super::*;
+
+    fn evaluation(aggregate: Aggregate, percentile: f32) -> EvaluationParams {
+        EvaluationParams {
+            aggregate,
+            percentile,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aggregate_takes_the_mean_the_worst_or_a_percentile() {
+        let scores = [4.0, -2.0, 10.0, 0.0, 3.0];
+
+        assert_eq!(evaluation(Aggregate::Mean, 0.0).aggregate(&scores), 3.0);
+        assert_eq!(evaluation(Aggregate::Min, 0.0).aggregate(&scores), -2.0);
+
+        let percentile = |p| evaluation(Aggregate::Percentile, p).aggregate(&scores);
+        assert_eq!(percentile(0.0), -2.0);
+        assert_eq!(percentile(0.25), 0.0);
+        assert_eq!(percentile(0.5), 3.0);
+        assert_eq!(percentile(1.0), 10.0);
+    }
+
+    #[test]
+    fn aggregate_of_a_single_course_is_its_score() {
+        for aggregate in [Aggregate::Mean, Aggregate::Min, Aggregate::Percentile] {
+            for percentile in [0.0, 0.25, 1.0] {
+                assert_eq!(evaluation(aggregate, percentile).aggregate(&[7.5]), 7.5);
+            }
+            assert_eq!(evaluation(aggregate, 0.5).aggregate(&[]), 0.0);
+        }
+    }
+
+    // Flies `world` hovering under the next gap until `over` says it's done
+    fn fly_until(world: &mut FlappyWorld, over: impl Fn(&FlappyWorld) -> bool) {
+        while !over(world) {
+            let observation = world.observe(0);
+            let flap = observation[4] < 0.45 && observation[3] < 0.6;
+            world.step(&[flap]).unwrap();
+        }
+    }
+
+    #[test]
+    fn the_tick_cap_ends_a_course_with_birds_alive() {
+        let evaluation = EvaluationParams {
+            max_ticks: 300,
+            ..Default::default()
+        };
+        let mut world = FlappyWorld::new(1, 5, WorldParams::default());
+
+        fly_until(&mut world, |world| evaluation.course_over(world));
+        assert_eq!(world.ticks(), 300);
+        assert_eq!(world.alive_count(), 1);
+    }
+
+    #[test]
+    fn the_pipe_cap_ends_a_course_with_birds_alive() {
+        let evaluation = EvaluationParams {
+            max_pipes: 3,
+            ..Default::default()
+        };
+        let mut world = FlappyWorld::new(1, 5, WorldParams::default());
+
+        fly_until(&mut world, |world| evaluation.course_over(world));
+        assert_eq!(world.score(), 3);
+        assert_eq!(world.alive_count(), 1);
+        assert!(world.ticks() < evaluation.max_ticks);
+    }
+
+    #[test]
+    fn never_solved_without_a_pipe_count() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let net = Net::new(
+            vec![NUM_OBSERVATIONS, 4, 1],
+            vec![Activation::Sigmoid; 2],
+            vec![LayerKind::Dense; 2],
+            &Initialization::default(),
+            &mut rng,
+        )
+        .unwrap();
+        let brain = Brain::Net(net);
+
+        let solved = SolvedParams::default();
+        assert_eq!(solved.pipes, None);
+        assert!(!solved.is_solved(&brain, WorldParams::default()).unwrap());
+    }
+}
@@ -0,0 +1,182 @@
+// Learning from recorded Human runs: every tick of a replay becomes a sample of
+// what the bird saw (the same observations bird_brain_system feeds brains) and
+// whether the player flapped, and a net is trained by backpropagation to do
+// the same. The result can start off an AI population.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::TrainingConfig;
+use crate::nn::{BackpropParams, Net};
+use crate::replay::Replay;
+use crate::world::FlappyWorld;
+
+// See `Config`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImitationConfig {
+    // Players flap on few ticks, so a net could learn to never flap and still
+    // be right most of the time. Balancing repeats the flaps until there are
+    // about as many as ticks without one.
+    pub balance: bool,
+    pub backprop: BackpropParams,
+}
+
+impl Default for ImitationConfig {
+    fn default() -> Self {
+        Self {
+            balance: true,
+            backprop: BackpropParams::default(),
+        }
+    }
+}
+
+impl ImitationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.backprop.validate().map_err(|e| format!("backprop.{}", e))
+    }
+}
+
+// (observation, [1 if the player flapped else 0]) for every tick of the run,
+// found by flying it again
+pub fn samples_from_replay(replay: &Replay) -> Vec<(Vec<f32>, Vec<f32>)> {
+    let mut world = FlappyWorld::new(1, replay.seed, replay.params);
+    let mut flaps = replay.flaps.iter().peekable();
+    let mut samples = Vec::new();
+    let mut observations = world.observations();
+    let mut rewards = Vec::new();
+
+    while world.birds()[0].alive && world.ticks() <= replay.ticks {
+        let flap = flaps.next_if_eq(&&world.ticks()).is_some();
+        samples.push((observations[0].to_vec(), vec![if flap { 1.0 } else { 0.0 }]));
+        world.step_into(&[flap], &mut observations, &mut rewards).expect("One bird");
+    }
+
+    samples
+}
+
+// Repeats the flap samples until there are about as many as those without
+// one, see `ImitationConfig::balance`
+fn balance(samples: &mut Vec<(Vec<f32>, Vec<f32>)>) {
+    let flapped = |sample: &(Vec<f32>, Vec<f32>)| sample.1[0] > 0.5;
+    let flaps = samples.iter().filter(|s| flapped(s)).count();
+    if flaps == 0 {
+        return;
+    }
+
+    let repeats = (samples.len() - flaps) / flaps;
+    let extra: Vec<_> = samples.iter().filter(|s| flapped(s)).cloned().collect();
+    for _ in 1..repeats {
+        samples.extend_from_slice(&extra);
+    }
+}
+
+// A net of the configured shape trained on all `replays`, and the mean loss
+// of every epoch
+pub fn imitate(
+    replays: &[Replay],
+    training: &TrainingConfig,
+    imitation: &ImitationConfig,
+    rng: &mut impl Rng,
+) -> Result<(Net, Vec<f32>), String> {
+    let mut samples: Vec<_> = replays.iter().flat_map(samples_from_replay).collect();
+    if samples.is_empty() {
+        return Err("The replays have no ticks to learn from".to_string());
+    }
+
+    if imitation.balance {
+        balance(&mut samples);
+    }
+
+    let mut net = Net::new(
+        training.layer_sizes.clone(),
+        training.activations.clone(),
+        training.layer_kinds(),
+        &training.init,
+        rng,
+    )
+    .map_err(|err| err.to_string())?;
+    let losses = net.train(&samples, &imitation.backprop, rng).map_err(|err| err.to_string())?;
+    Ok((net, losses))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::world::WorldParams;
+
+    use super::*;
+
+    // A run that hovers under the gaps for a while, then stops flapping
+    fn recorded_run(seed: u64) -> Replay {
+        let mut world = FlappyWorld::new(1, seed, WorldParams::default());
+        let mut replay = Replay::new(seed, *world.params());
+        loop {
+            let observation = world.observe(0);
+            let flap = world.ticks() < 600 && observation[4] < 0.45 && observation[3] < 0.6;
+            if flap {
+                replay.flaps.push(world.ticks());
+            }
+            if world.step(&[flap]).unwrap().2 {
+                break;
+            }
+        }
+        replay.ticks = world.ticks();
+        replay.score = world.score();
+        replay
+    }
+
+    #[test]
+    fn every_tick_of_a_replay_is_a_sample_labelled_with_its_flap() {
+        let replay = recorded_run(11);
+        assert!(!replay.flaps.is_empty());
+
+        let samples = samples_from_replay(&replay);
+        assert_eq!(samples.len() as u64, replay.ticks);
+
+        let mut world = FlappyWorld::new(1, replay.seed, replay.params);
+        for (tick, (observation, label)) in samples.iter().enumerate() {
+            let flap = replay.flaps.contains(&(tick as u64));
+            assert_eq!(label, &vec![if flap { 1.0 } else { 0.0 }], "tick {}", tick);
+            assert_eq!(observation[..], world.observe(0)[..], "tick {}", tick);
+            world.step(&[flap]).unwrap();
+        }
+    }
+
+    #[test]
+    fn balancing_evens_out_flaps_and_ticks_without_one() {
+        let mut samples = samples_from_replay(&recorded_run(12));
+        let count = |samples: &[(Vec<f32>, Vec<f32>)]| {
+            let flaps = samples.iter().filter(|s| s.1[0] > 0.5).count();
+            (flaps, samples.len() - flaps)
+        };
+        let (flaps, others) = count(&samples);
+        assert!(flaps * 2 < others, "{} flaps, {} without", flaps, others);
+
+        balance(&mut samples);
+        let (balanced_flaps, balanced_others) = count(&samples);
+        assert_eq!(balanced_others, others);
+        assert!(balanced_flaps.abs_diff(others) < flaps, "{} flaps, {} without", balanced_flaps, others);
+
+        // Nothing to repeat without any flaps
+        let mut never = vec![(vec![0.0; 5], vec![0.0]); 10];
+        balance(&mut never);
+        assert_eq!(never.len(), 10);
+    }
+
+    #[test]
+    fn imitate_trains_a_net_on_the_replays() {
+        let mut rng = ChaCha8Rng::seed_from_u64(13);
+        let replays = [recorded_run(14), recorded_run(15)];
+
+        let (net, losses) =
+            imitate(&replays, &TrainingConfig::default(), &ImitationConfig::default(), &mut rng).unwrap();
+        assert_eq!(net.layer_sizes(), TrainingConfig::default().layer_sizes);
+        assert!(losses.last() < losses.first(), "{:?}", losses);
+
+        let empty = imitate(&[], &TrainingConfig::default(), &ImitationConfig::default(), &mut rng);
+        assert!(empty.is_err());
+    }
+}
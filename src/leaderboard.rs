@@ -0,0 +1,332 @@
+// Top scores kept across launches, in a small JSON file:
+// {"format": "flappy-highscores", "version": 1, "entries": [...]}
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+pub const LEADERBOARD_FORMAT_VERSION: u16 = 1;
+pub const MAX_ENTRIES: usize = 10;
+
+const JSON_FORMAT_NAME: &str = "flappy-highscores";
+
+#[derive(Debug)]
+pub enum LeaderboardError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    NotALeaderboard,
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for LeaderboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeaderboardError::Io(err) => write!(f, "{}", err),
+            LeaderboardError::Json(err) => write!(f, "Invalid high score JSON: {}", err),
+            LeaderboardError::NotALeaderboard => write!(f, "Not a high score file"),
+            LeaderboardError::UnsupportedVersion(version) => write!(
+                f,
+                "High score file version {} is not supported (expected {})",
+                version, LEADERBOARD_FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LeaderboardError {}
+
+impl From<io::Error> for LeaderboardError {
+    fn from(err: io::Error) -> Self {
+        LeaderboardError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for LeaderboardError {
+    fn from(err: serde_json::Error) -> Self {
+        LeaderboardError::Json(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScoreMode {
+    Human,
+    AiChampion,
+    // A Champion mode run
+    Champion,
+}
+
+impl fmt::Display for ScoreMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScoreMode::Human => write!(f, "Human"),
+            ScoreMode::AiChampion => write!(f, "AI"),
+            ScoreMode::Champion => write!(f, "Champion"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: u32,
+    pub mode: ScoreMode,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl LeaderboardEntry {
+    pub fn now(name: &str, score: u32, mode: ScoreMode) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Self {
+            name: name.to_string(),
+            score,
+            mode,
+            timestamp,
+        }
+    }
+
+    // YYYY-MM-DD (UTC)
+    pub fn date(&self) -> String {
+        let (year, month, day) = civil_from_days((self.timestamp / 86_400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Leaderboard {
+    // Best first, never more than MAX_ENTRIES
+    entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLeaderboard {
+    format: String,
+    version: u64,
+    entries: Vec<LeaderboardEntry>,
+}
+
+impl Leaderboard {
+    pub fn entries(&self) -> &[LeaderboardEntry] {
+        &self.entries
+    }
+
+    pub fn best_score(&self) -> u32 {
+        self.entries.first().map_or(0, |e| e.score)
+    }
+
+    // Whether `entry` would make it onto the board. The same name and mode
+    // can't hold the same score twice.
+    pub fn qualifies(&self, entry: &LeaderboardEntry) -> bool {
+        let duplicate = self
+            .entries
+            .iter()
+            .any(|e| e.name == entry.name && e.mode == entry.mode && e.score == entry.score);
+
+        entry.score > 0
+            && !duplicate
+            && (self.entries.len() < MAX_ENTRIES
+                || self.entries.last().is_some_and(|e| entry.score > e.score))
+    }
+
+    // Returns the rank (0 is best) the entry got, or None if it didn't make it.
+    // Ties go to whoever got there first.
+    pub fn insert(&mut self, entry: LeaderboardEntry) -> Option<usize> {
+        if !self.qualifies(&entry) {
+            return None;
+        }
+
+        let rank = self
+            .entries
+            .iter()
+            .position(|e| entry.score > e.score)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(rank, entry);
+        self.entries.truncate(MAX_ENTRIES);
+
+        Some(rank)
+    }
+
+    pub fn to_json(&self) -> String {
+        let file = JsonLeaderboard {
+            format: JSON_FORMAT_NAME.to_string(),
+            version: LEADERBOARD_FORMAT_VERSION as u64,
+            entries: self.entries.clone(),
+        };
+        serde_json::to_string_pretty(&file).expect("Leaderboard is always serializable")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, LeaderboardError> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        if value.get("format").and_then(|f| f.as_str()) != Some(JSON_FORMAT_NAME) {
+            return Err(LeaderboardError::NotALeaderboard);
+        }
+
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or(LeaderboardError::NotALeaderboard)?;
+        if version != LEADERBOARD_FORMAT_VERSION as u64 {
+            return Err(LeaderboardError::UnsupportedVersion(version));
+        }
+
+        // Don't trust the file to be sorted or short enough
+        let file: JsonLeaderboard = serde_json::from_value(value)?;
+        let mut board = Leaderboard::default();
+        for entry in file.entries {
+            board.insert(entry);
+        }
+
+        Ok(board)
+    }
+
+    // Writes to a temporary file first so a crash can't leave half a file behind
+    pub fn save(&self, path: &Path) -> Result<(), LeaderboardError> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, self.to_json())?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, LeaderboardError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    // Never fails: a missing file is an empty board, and an unreadable one is
+    // moved aside to `<path>.corrupt` (so it isn't overwritten) before starting
+    // empty. The message says what happened in the latter case.
+    pub fn load_or_default(path: &Path) -> (Self, Option<String>) {
+        match Self::load(path) {
+            Ok(board) => (board, None),
+            Err(LeaderboardError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                (Self::default(), None)
+            }
+            Err(err) => {
+                let mut backup = PathBuf::from(path);
+                backup.as_mut_os_string().push(".corrupt");
+
+                let message = match fs::rename(path, &backup) {
+                    Ok(()) => format!(
+                        "High scores in {} unreadable ({}), moved to {}",
+                        path.display(),
+                        err,
+                        backup.display()
+                    ),
+                    Err(_) => format!("High scores in {} unreadable ({})", path.display(), err),
+                };
+                (Self::default(), Some(message))
+            }
+        }
+    }
+}
+
+// Days since 1970-01-01 to a (year, month, day) date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: u32) -> LeaderboardEntry {
+        LeaderboardEntry::now(name, score, ScoreMode::Human)
+    }
+
+    fn full_board() -> Leaderboard {
+        let mut board = Leaderboard::default();
+        for score in 1..=MAX_ENTRIES as u32 {
+            board.insert(entry(&format!("Player {}", score), score * 10));
+        }
+        board
+    }
+
+    #[test]
+    fn zero_never_qualifies() {
+        assert!(!Leaderboard::default().qualifies(&entry("A", 0)));
+    }
+
+    #[test]
+    fn anything_qualifies_while_there_is_room() {
+        let mut board = Leaderboard::default();
+        board.insert(entry("A", 50));
+        assert!(board.qualifies(&entry("B", 1)));
+        assert!(board.qualifies(&entry("A", 49)));
+    }
+
+    #[test]
+    fn a_full_board_takes_only_better_scores() {
+        let board = full_board();
+        assert!(!board.qualifies(&entry("B", 10)));
+        assert!(!board.qualifies(&entry("B", 5)));
+        assert!(board.qualifies(&entry("B", 11)));
+    }
+
+    #[test]
+    fn exact_duplicates_are_rejected() {
+        let mut board = Leaderboard::default();
+        assert_eq!(board.insert(entry("A", 30)), Some(0));
+        assert!(!board.qualifies(&entry("A", 30)));
+        assert_eq!(board.insert(entry("A", 30)), None);
+
+        // Another name or mode with the same score is a different run
+        assert_eq!(board.insert(entry("B", 30)), Some(1));
+        let champion = LeaderboardEntry::now("A", 30, ScoreMode::Champion);
+        assert_eq!(board.insert(champion), Some(2));
+        assert_eq!(board.entries().len(), 3);
+    }
+
+    #[test]
+    fn entries_are_kept_best_first_with_ties_in_arrival_order() {
+        let mut board = Leaderboard::default();
+        assert_eq!(board.insert(entry("A", 20)), Some(0));
+        assert_eq!(board.insert(entry("B", 40)), Some(0));
+        assert_eq!(board.insert(entry("C", 20)), Some(2));
+        assert_eq!(board.insert(entry("D", 30)), Some(1));
+
+        let names: Vec<&str> = board.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["B", "D", "A", "C"]);
+        assert_eq!(board.best_score(), 40);
+    }
+
+    #[test]
+    fn the_board_is_cut_at_max_entries() {
+        let mut board = full_board();
+        assert_eq!(board.entries().len(), MAX_ENTRIES);
+
+        assert_eq!(board.insert(entry("Best", 1000)), Some(0));
+        assert_eq!(board.entries().len(), MAX_ENTRIES);
+        // The worst one dropped off
+        assert_eq!(board.entries().last().unwrap().score, 20);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let board = full_board();
+        let loaded = Leaderboard::from_json(&board.to_json()).unwrap();
+        let scores = |b: &Leaderboard| b.entries().iter().map(|e| e.score).collect::<Vec<_>>();
+        assert_eq!(scores(&loaded), scores(&board));
+    }
+}
@@ -0,0 +1,23 @@
+// Bevy systems routinely take many parameters and complex query types
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+pub mod brain;
+pub mod brain_file;
+pub mod checkpoint;
+pub mod components;
+pub mod config;
+pub mod constants;
+pub mod fitness;
+pub mod imitation;
+pub mod leaderboard;
+pub mod neat;
+pub mod nn;
+pub mod onnx;
+pub mod replay;
+pub mod resources;
+pub mod selection;
+pub mod setup;
+pub mod systems;
+pub mod ui;
+pub mod utils;
+pub mod world;
@@ -1,26 +1,168 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
-use crate::constants::{WINDOW_HEIGHT, WINDOW_WIDTH};
-use resources::*;
-use setup::setup;
-use systems::*;
-
-mod components;
-mod constants;
-mod resources;
-mod setup;
-mod systems;
-mod utils;
-mod nn;
-mod ui;
+use flappy_rust::brain_file::load_brain;
+use flappy_rust::checkpoint::Checkpoint;
+use flappy_rust::config::Config;
+use flappy_rust::constants::{FIXED_TIMESTEP, WINDOW_HEIGHT, WINDOW_WIDTH};
+use flappy_rust::replay::Replay;
+use flappy_rust::resources::*;
+use flappy_rust::setup::setup;
+use flappy_rust::systems::*;
 
 use bevy_egui::EguiPlugin;
-use ui::{UiState, ui_system};
+use flappy_rust::ui::{UiState, brain_view_ui, ui_system};
+
+const HIGH_SCORES_PATH: &str = "highscores.json";
+
+const USAGE: &str = "Usage: flappy-rust [--config FILE] [--resume CHECKPOINT | --replay REPLAY | --champion BRAIN]";
+
+#[derive(Default)]
+struct Args {
+    config: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    champion: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let value = args.next().map(PathBuf::from).ok_or_else(|| format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--config" => parsed.config = Some(value),
+            "--resume" => parsed.resume = Some(value),
+            "--replay" => parsed.replay = Some(value),
+            "--champion" => parsed.champion = Some(value),
+            _ => return Err(format!("Unknown argument: {}", flag)),
+        }
+    }
+
+    let starts = [&parsed.resume, &parsed.replay, &parsed.champion];
+    if starts.iter().filter(|start| start.is_some()).count() > 1 {
+        return Err("Only one of --resume, --replay and --champion can be used".to_string());
+    }
+    // The checkpoint brings its own config, like in flappy-train
+    if parsed.resume.is_some() && parsed.config.is_some() {
+        return Err("--config can't be used with --resume, the checkpoint's is kept".to_string());
+    }
+
+    Ok(parsed)
+}
 
 fn main() {
-    App::new()
-        .init_resource::<Game>()
-        .init_resource::<SimulationState>()
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let checkpoint = args.resume.as_ref().map(|path| match Checkpoint::load(path) {
+        Ok(checkpoint) => {
+            println!("Resuming generation {} from {}", checkpoint.generation, path.display());
+            checkpoint
+        }
+        Err(err) => {
+            eprintln!("Failed to load checkpoint {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    });
+
+    // A resumed run carries on with the knobs it started with, flappy.toml
+    // isn't even read then
+    let config = match &checkpoint {
+        Some(checkpoint) => checkpoint.config.clone(),
+        None => match Config::load_or_default(args.config.as_deref()) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load config: {}", err);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let mut sim_state = SimulationState {
+        birds_alive: config.training.population,
+        ..default()
+    };
+    let mut rng = config.seed.map_or_else(SimRng::default, SimRng::new);
+    let mut resume = None;
+    let mut playback = None;
+
+    if let Some(checkpoint) = checkpoint {
+        // And with the randomness it had got to
+        rng = checkpoint.rng.clone();
+        checkpoint.restore_sim_state(&mut sim_state);
+        resume = Some(ResumeFrom(checkpoint));
+    }
+
+    // Watch a recorded Human run, press Space to start it
+    if let Some(path) = &args.replay {
+        match Replay::load(path) {
+            Ok(replay) => {
+                println!("Watching {} (score {})", path.display(), replay.score);
+                sim_state.mode = GameMode::Human;
+                playback = Some(ReplayPlayback::new(replay));
+            }
+            Err(err) => {
+                eprintln!("Failed to load replay {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Watch a saved brain fly, press Space to start and to retry on a new course
+    if let Some(path) = &args.champion {
+        match load_brain(path) {
+            Ok(brain) => {
+                println!("Champion {} takes off", path.display());
+                sim_state.mode = GameMode::Champion;
+                sim_state.best_brain = Some(brain);
+            }
+            Err(err) => {
+                eprintln!("Failed to load brain {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let high_scores = HighScores::load(PathBuf::from(HIGH_SCORES_PATH));
+
+    let mut app = App::new();
+    if let Some(resume) = resume {
+        app.insert_resource(resume);
+    }
+    if let Some(playback) = playback {
+        app.insert_resource(playback);
+    }
+
+    app
+        .insert_resource(Game {
+            high_score: high_scores.board.best_score(),
+            ..default()
+        })
+        .insert_resource(high_scores)
+        .insert_resource(sim_state)
+        .insert_resource(config)
+        .insert_resource(rng)
+        .init_resource::<CheckpointSettings>()
+        .init_resource::<ReplayRecorder>()
+        .init_resource::<FlapInput>()
+        .init_resource::<BrainBatch>()
+        .init_resource::<NeatState>()
         .init_resource::<UiState>()
+        .init_resource::<BrainView>()
+        .insert_resource(Time::<Fixed>::from_seconds(FIXED_TIMESTEP))
+        .add_event::<SoundEvent>()
+        .add_event::<GenerationEvent>()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Flappy Rust".to_string(),
@@ -34,21 +176,36 @@ fn main() {
         .add_systems(Startup, setup)
         .add_systems(Update, blink_space_bar_text.run_if(is_game_not_active))
         .add_systems(Update, move_background.run_if(is_game_active))
-        .add_systems(Update, move_ground.run_if(is_game_active))
         .add_systems(Update, animate_bird.run_if(is_game_active))
         .add_systems(Update, start_game.run_if(is_game_not_active))
-        .add_systems(Update, gravity.run_if(is_game_active))
-        .add_systems(Update, jump.run_if(is_game_active))
-        .add_systems(Update, pipes.run_if(is_game_active))
-        .add_systems(Update, score.run_if(is_game_active))
+        .add_systems(Update, queue_jump.run_if(is_game_active))
         .add_systems(Update, render_score.run_if(is_game_active))
         .add_systems(Update, render_high_score.run_if(is_game_not_active))
+        .add_systems(Update, render_leaderboard)
         .add_systems(Update, reset_game_after_game_over.run_if(is_game_over))
-        // AI Systems
-        .add_systems(Update, bird_brain_system.run_if(is_game_active))
-        .add_systems(Update, check_alive_and_next_gen.run_if(is_game_active))
+        .add_systems(Update, play_sounds)
+        // Simulation runs on a fixed tick, in a fixed order
+        .add_systems(
+            FixedUpdate,
+            (
+                bird_brain_system,
+                play_replay,
+                jump,
+                record_replay,
+                step_world,
+                finish_replay,
+                submit_score,
+                move_ground,
+                check_alive_and_next_gen,
+                save_checkpoints,
+            )
+                .chain()
+                .run_if(is_game_active),
+        )
+        .add_systems(Update, (sync_birds, sync_pipes))
         .add_systems(Update, update_gen_ui)
         .add_systems(Update, toggle_game_mode)
         .add_systems(Update, ui_system)
+        .add_systems(Update, brain_view_ui.after(ui_system))
         .run();
 }
@@ -0,0 +1,814 @@
+// NEAT (NeuroEvolution of Augmenting Topologies): brains that start with the
+// inputs wired straight to the outputs and grow hidden nodes and connections as
+// they evolve. Connection genes carry innovation numbers so genomes of
+// different shapes can still be lined up for crossover and compared when
+// sorting them into species.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::nn::{Activation, MutationParams, NetError, Scratch};
+
+// Random pairs of nodes add-connection looks at before giving up
+const ADD_CONNECTION_TRIES: usize = 20;
+// Chance a gene disabled in either parent stays disabled in the child
+const INHERIT_DISABLED_RATE: f64 = 0.75;
+
+// Speciation and structural mutation knobs, see `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NeatParams {
+    // Genomes closer than this to a species' representative belong to it
+    pub compatibility_threshold: f32,
+    // Weights of the compatibility distance terms
+    pub excess_coefficient: f32,
+    pub disjoint_coefficient: f32,
+    pub weight_coefficient: f32,
+    // Chances per child of growing a connection or a node
+    pub add_connection_rate: f32,
+    pub add_node_rate: f32,
+    // Chance a child has two parents rather than being a copy of one
+    pub crossover_rate: f32,
+    // Share of each species (best first) allowed to breed
+    pub survival_threshold: f32,
+    pub hidden_activation: Activation,
+    pub output_activation: Activation,
+}
+
+impl Default for NeatParams {
+    fn default() -> Self {
+        Self {
+            compatibility_threshold: 3.0,
+            excess_coefficient: 1.0,
+            disjoint_coefficient: 1.0,
+            weight_coefficient: 0.4,
+            add_connection_rate: 0.05,
+            add_node_rate: 0.03,
+            crossover_rate: 0.75,
+            survival_threshold: 0.2,
+            hidden_activation: Activation::Sigmoid,
+            output_activation: Activation::Sigmoid,
+        }
+    }
+}
+
+impl NeatParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.compatibility_threshold.is_finite() && self.compatibility_threshold > 0.0) {
+            return Err(format!(
+                "compatibility_threshold must be positive, got {}",
+                self.compatibility_threshold
+            ));
+        }
+
+        let coefficients = [
+            ("excess_coefficient", self.excess_coefficient),
+            ("disjoint_coefficient", self.disjoint_coefficient),
+            ("weight_coefficient", self.weight_coefficient),
+        ];
+        for (name, value) in coefficients {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{} can't be negative, got {}", name, value));
+            }
+        }
+
+        let rates = [
+            ("add_connection_rate", self.add_connection_rate),
+            ("add_node_rate", self.add_node_rate),
+            ("crossover_rate", self.crossover_rate),
+        ];
+        for (name, value) in rates {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be within 0..1, got {}", name, value));
+            }
+        }
+
+        if !(self.survival_threshold > 0.0 && self.survival_threshold <= 1.0) {
+            return Err(format!(
+                "survival_threshold must be more than 0 and at most 1, got {}",
+                self.survival_threshold
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    Output,
+    Hidden,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeGene {
+    pub id: usize,
+    pub kind: NodeKind,
+    // Unused for inputs, they pass their value on as is
+    pub bias: f32,
+    pub activation: Activation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionGene {
+    pub innovation: usize,
+    pub from: usize,
+    pub to: usize,
+    pub weight: f32,
+    pub enabled: bool,
+}
+
+// Hands out innovation numbers and node ids for the whole population, so the
+// same structural change gets the same numbers in every genome that makes it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Innovations {
+    next_innovation: usize,
+    next_node: usize,
+    // (from, to) -> innovation
+    connections: BTreeMap<(usize, usize), usize>,
+    // Innovation of a split connection -> the node put in its place
+    splits: BTreeMap<usize, usize>,
+}
+
+impl Innovations {
+    pub fn connection(&mut self, from: usize, to: usize) -> usize {
+        let next = &mut self.next_innovation;
+        *self.connections.entry((from, to)).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    fn split(&mut self, innovation: usize) -> usize {
+        let next = &mut self.next_node;
+        *self.splits.entry(innovation).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    fn new_node(&mut self) -> usize {
+        self.next_node += 1;
+        self.next_node - 1
+    }
+
+    // Takes note of a genome from elsewhere (a brain file), so new genes
+    // don't reuse its numbers
+    pub fn record(&mut self, genome: &Genome) {
+        for node in genome.nodes.iter() {
+            self.next_node = self.next_node.max(node.id + 1);
+        }
+        for gene in genome.connections.iter() {
+            self.connections.entry((gene.from, gene.to)).or_insert(gene.innovation);
+            self.next_innovation = self.next_innovation.max(gene.innovation + 1);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Genome {
+    n_inputs: usize,
+    n_outputs: usize,
+    // In evaluation order with the inputs first (ids 0..n_inputs), every
+    // connection leads to a node further down. Output ids follow the inputs.
+    nodes: Vec<NodeGene>,
+    // Sorted by innovation
+    connections: Vec<ConnectionGene>,
+    // Evolved by self-adaptive mutation, None until it first mutates the genome
+    step_size: Option<f32>,
+}
+
+impl Genome {
+    // Every input wired straight to every output, no hidden nodes
+    pub fn new(
+        n_inputs: usize,
+        n_outputs: usize,
+        params: &NeatParams,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) -> Self {
+        innovations.next_node = innovations.next_node.max(n_inputs + n_outputs);
+
+        let inputs = (0..n_inputs).map(|id| NodeGene {
+            id,
+            kind: NodeKind::Input,
+            bias: 0.0,
+            activation: Activation::Linear,
+        });
+        let outputs = (n_inputs..n_inputs + n_outputs).map(|id| NodeGene {
+            id,
+            kind: NodeKind::Output,
+            bias: rng.gen_range(-1.0..1.0),
+            activation: params.output_activation,
+        });
+        let nodes = inputs.chain(outputs).collect();
+
+        let mut connections = Vec::new();
+        for from in 0..n_inputs {
+            for to in n_inputs..n_inputs + n_outputs {
+                connections.push(ConnectionGene {
+                    innovation: innovations.connection(from, to),
+                    from,
+                    to,
+                    weight: rng.gen_range(-1.0..1.0),
+                    enabled: true,
+                });
+            }
+        }
+        connections.sort_by_key(|c| c.innovation);
+
+        Self {
+            n_inputs,
+            n_outputs,
+            nodes,
+            connections,
+            step_size: None,
+        }
+    }
+
+    // Node values are kept in `scratch` by node id, the outputs are returned
+    // from it
+    pub fn predict_into<'s>(
+        &self,
+        inputs: &[f32],
+        scratch: &'s mut Scratch,
+    ) -> Result<&'s [f32], NetError> {
+        if inputs.len() != self.n_inputs {
+            return Err(NetError::WrongInputSize {
+                expected: self.n_inputs,
+                found: inputs.len(),
+            });
+        }
+
+        let values = &mut scratch.current;
+        values.resize(self.nodes.iter().map(|n| n.id + 1).max().unwrap_or(0), 0.0);
+        values[..self.n_inputs].copy_from_slice(inputs);
+
+        // Every node's inputs come from further up, so one pass is enough
+        for node in self.nodes[self.n_inputs..].iter() {
+            let sum = self
+                .connections
+                .iter()
+                .filter(|c| c.enabled && c.to == node.id)
+                .fold(node.bias, |total, c| total + c.weight * values[c.from]);
+            values[node.id] = node.activation.apply(sum);
+        }
+
+        scratch.next.clear();
+        let outputs = self.n_inputs..self.n_inputs + self.n_outputs;
+        scratch.next.extend(outputs.map(|id| scratch.current[id]));
+        Ok(&scratch.next)
+    }
+
+    pub fn mutate(
+        &mut self,
+        params: &NeatParams,
+        mutation: &MutationParams,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) {
+        self.mutate_weights(mutation, rng);
+
+        if rng.gen_range(0.0..1.0) < params.add_connection_rate {
+            self.add_connection(innovations, rng);
+        }
+        if rng.gen_range(0.0..1.0) < params.add_node_rate {
+            self.add_node(params, innovations, rng);
+        }
+    }
+
+    // Weights and biases only, the topology stays as it is
+    pub fn mutate_weights(&mut self, mutation: &MutationParams, rng: &mut impl Rng) {
+        let scale = mutation.step(&mut self.step_size, rng);
+        for gene in self.connections.iter_mut() {
+            mutation.perturb(&mut gene.weight, scale, rng);
+        }
+        for node in self.nodes[self.n_inputs..].iter_mut() {
+            mutation.perturb(&mut node.bias, scale, rng);
+        }
+    }
+
+    // Connects two nodes that aren't yet, keeping the evaluation order intact
+    fn add_connection(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        for _ in 0..ADD_CONNECTION_TRIES {
+            let from_pos = rng.gen_range(0..self.nodes.len());
+            let to_pos = rng.gen_range(0..self.nodes.len());
+            if from_pos >= to_pos
+                || to_pos < self.n_inputs
+                || self.nodes[from_pos].kind == NodeKind::Output
+            {
+                continue;
+            }
+
+            let (from, to) = (self.nodes[from_pos].id, self.nodes[to_pos].id);
+            if self.connections.iter().any(|c| c.from == from && c.to == to) {
+                continue;
+            }
+
+            self.insert_connection(ConnectionGene {
+                innovation: innovations.connection(from, to),
+                from,
+                to,
+                weight: rng.gen_range(-1.0..1.0),
+                enabled: true,
+            });
+            return;
+        }
+    }
+
+    // Splits a random connection with a new hidden node. The old connection is
+    // disabled, the new ones start out close to it: weight 1 into the node and
+    // the old weight out of it.
+    fn add_node(&mut self, params: &NeatParams, innovations: &mut Innovations, rng: &mut impl Rng) {
+        let enabled = self.connections.iter().filter(|c| c.enabled).count();
+        if enabled == 0 {
+            return;
+        }
+        let pick = rng.gen_range(0..enabled);
+        let Some(old) = self.connections.iter_mut().filter(|c| c.enabled).nth(pick) else {
+            return;
+        };
+        old.enabled = false;
+        let old = old.clone();
+
+        let mut id = innovations.split(old.innovation);
+        if self.position(id).is_some() {
+            // This genome split the same connection before
+            id = innovations.new_node();
+        }
+
+        let from_pos = self.position(old.from).expect("Connections only join existing nodes");
+        self.nodes.insert(
+            (from_pos + 1).max(self.n_inputs),
+            NodeGene {
+                id,
+                kind: NodeKind::Hidden,
+                bias: 0.0,
+                activation: params.hidden_activation,
+            },
+        );
+
+        self.insert_connection(ConnectionGene {
+            innovation: innovations.connection(old.from, id),
+            from: old.from,
+            to: id,
+            weight: 1.0,
+            enabled: true,
+        });
+        self.insert_connection(ConnectionGene {
+            innovation: innovations.connection(id, old.to),
+            from: id,
+            to: old.to,
+            weight: old.weight,
+            enabled: true,
+        });
+    }
+
+    fn insert_connection(&mut self, gene: ConnectionGene) {
+        let at = self.connections.partition_point(|c| c.innovation < gene.innovation);
+        self.connections.insert(at, gene);
+    }
+
+    fn position(&self, id: usize) -> Option<usize> {
+        self.nodes.iter().position(|n| n.id == id)
+    }
+
+    // A child of this genome and a less fit `other`. It gets the topology of
+    // this one, the genes both share take their weight from either parent.
+    pub fn crossover(&self, other: &Genome, rng: &mut impl Rng) -> Genome {
+        let mut child = self.clone();
+
+        for gene in child.connections.iter_mut() {
+            let Ok(index) = other
+                .connections
+                .binary_search_by_key(&gene.innovation, |c| c.innovation)
+            else {
+                continue;
+            };
+            let theirs = &other.connections[index];
+            if theirs.from != gene.from || theirs.to != gene.to {
+                continue;
+            }
+
+            if rng.gen_bool(0.5) {
+                gene.weight = theirs.weight;
+            }
+            if !gene.enabled || !theirs.enabled {
+                gene.enabled = !rng.gen_bool(INHERIT_DISABLED_RATE);
+            }
+        }
+
+        for node in child.nodes[child.n_inputs..].iter_mut() {
+            if let Some(theirs) = other.nodes.iter().find(|n| n.id == node.id) {
+                if rng.gen_bool(0.5) {
+                    node.bias = theirs.bias;
+                }
+            }
+        }
+
+        child
+    }
+
+    // Compatibility distance: how many genes only one of the two has (excess
+    // ones past the end of the other's innovations, disjoint ones in between)
+    // and how far apart the weights of the shared ones are
+    pub fn distance(&self, other: &Genome, params: &NeatParams) -> f32 {
+        let (ours, theirs) = (&self.connections, &other.connections);
+        let (mut i, mut j) = (0, 0);
+        let (mut matching, mut disjoint, mut weight_difference) = (0, 0, 0.0);
+
+        while i < ours.len() && j < theirs.len() {
+            match ours[i].innovation.cmp(&theirs[j].innovation) {
+                Ordering::Equal => {
+                    matching += 1;
+                    weight_difference += (ours[i].weight - theirs[j].weight).abs();
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    disjoint += 1;
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    disjoint += 1;
+                    j += 1;
+                }
+            }
+        }
+        let excess = (ours.len() - i) + (theirs.len() - j);
+
+        let genes = ours.len().max(theirs.len()).max(1) as f32;
+        let average_difference = if matching > 0 {
+            weight_difference / matching as f32
+        } else {
+            0.0
+        };
+
+        params.excess_coefficient * excess as f32 / genes
+            + params.disjoint_coefficient * disjoint as f32 / genes
+            + params.weight_coefficient * average_difference
+    }
+
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        self.n_outputs
+    }
+
+    pub fn hidden_count(&self) -> usize {
+        self.nodes.iter().filter(|n| n.kind == NodeKind::Hidden).count()
+    }
+
+    pub fn enabled_connections(&self) -> usize {
+        self.connections.iter().filter(|c| c.enabled).count()
+    }
+
+    pub fn step_size(&self) -> Option<f32> {
+        self.step_size
+    }
+
+    // Genomes from `new` and evolution are always well formed, but one read
+    // from disk may not be: nodes in evaluation order starting with the inputs,
+    // every output present, and connections sorted by innovation leading from
+    // a node to one further down
+    pub fn check_shape(&self) -> Result<(), String> {
+        if self.n_inputs < 1 || self.n_outputs < 1 {
+            return Err("Need at least one input and one output".to_string());
+        }
+
+        let outputs = self.n_inputs..self.n_inputs + self.n_outputs;
+        for (pos, node) in self.nodes.iter().enumerate() {
+            if pos < self.n_inputs {
+                if node.kind != NodeKind::Input || node.id != pos {
+                    return Err(format!("Node {} should be input {}", node.id, pos));
+                }
+            } else if node.kind == NodeKind::Input {
+                return Err(format!("Input node {} comes after other nodes", node.id));
+            } else if (node.kind == NodeKind::Output) != outputs.contains(&node.id) {
+                return Err(format!("Node {} has the wrong kind for its id", node.id));
+            }
+
+            if self.nodes[..pos].iter().any(|n| n.id == node.id) {
+                return Err(format!("Node {} appears twice", node.id));
+            }
+        }
+        if let Some(id) = outputs.clone().find(|&id| self.position(id).is_none()) {
+            return Err(format!("Output node {} is missing", id));
+        }
+
+        if self.connections.windows(2).any(|w| w[0].innovation >= w[1].innovation) {
+            return Err("Connections must be sorted by unique innovation".to_string());
+        }
+        for gene in self.connections.iter() {
+            let (Some(from), Some(to)) = (self.position(gene.from), self.position(gene.to)) else {
+                return Err(format!("Connection {} joins missing nodes", gene.innovation));
+            };
+            if from >= to || self.nodes[from].kind == NodeKind::Output {
+                return Err(format!(
+                    "Connection {} goes against the evaluation order",
+                    gene.innovation
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Species {
+    pub id: usize,
+    // Genomes are compared against it to find their species
+    representative: Genome,
+    // Members in the last generation
+    pub size: usize,
+}
+
+// Everything NEAT keeps between generations
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Neat {
+    pub innovations: Innovations,
+    species: Vec<Species>,
+    next_species_id: usize,
+}
+
+impl Neat {
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    // Sorts the finished generation into species and breeds as many genomes
+    // from them. Fitness is shared within a species, so each one gets
+    // offspring in proportion to its average fitness rather than its size and
+    // new structures get a few generations to pay off.
+    pub fn next_generation(
+        &mut self,
+        population: Vec<(Genome, f32)>,
+        params: &NeatParams,
+        mutation: &MutationParams,
+        rng: &mut impl Rng,
+    ) -> Vec<Genome> {
+        if population.is_empty() {
+            return Vec::new();
+        }
+
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); self.species.len()];
+        for (index, (genome, _)) in population.iter().enumerate() {
+            let compatible = self.species.iter().position(|s| {
+                genome.distance(&s.representative, params) < params.compatibility_threshold
+            });
+            match compatible {
+                Some(species) => members[species].push(index),
+                None => {
+                    self.species.push(Species {
+                        id: self.next_species_id,
+                        representative: genome.clone(),
+                        size: 0,
+                    });
+                    self.next_species_id += 1;
+                    members.push(vec![index]);
+                }
+            }
+        }
+
+        // Species nobody belongs to anymore die out
+        let (mut species, mut members): (Vec<_>, Vec<_>) = self
+            .species
+            .drain(..)
+            .zip(members)
+            .filter(|(_, m)| !m.is_empty())
+            .unzip();
+
+        let fitness = |index: usize| population[index].1.max(0.0);
+        let shared: Vec<f32> = members
+            .iter()
+            .map(|m| m.iter().map(|&i| fitness(i)).sum::<f32>() / m.len() as f32)
+            .collect();
+        let offspring = allot(&shared, population.len());
+
+        let mut next = Vec::with_capacity(population.len());
+        for ((entry, members), count) in species.iter_mut().zip(members.iter_mut()).zip(offspring) {
+            members.sort_by(|&a, &b| fitness(b).total_cmp(&fitness(a)));
+            entry.representative = population[members[0]].0.clone();
+            entry.size = members.len();
+
+            if count == 0 {
+                continue;
+            }
+
+            // The species champion carries over unchanged
+            next.push(population[members[0]].0.clone());
+
+            let breeders = (members.len() as f32 * params.survival_threshold).ceil() as usize;
+            let parents = &members[..breeders.clamp(1, members.len())];
+            for _ in 1..count {
+                let a = *parents.choose(rng).unwrap();
+                let b = *parents.choose(rng).unwrap();
+
+                let mut child = if a != b && rng.gen_range(0.0..1.0) < params.crossover_rate {
+                    let (fitter, other) = if fitness(a) >= fitness(b) { (a, b) } else { (b, a) };
+                    population[fitter].0.crossover(&population[other].0, rng)
+                } else {
+                    population[a].0.clone()
+                };
+                child.mutate(params, mutation, &mut self.innovations, rng);
+                next.push(child);
+            }
+        }
+
+        self.species = species;
+        next
+    }
+}
+
+// Splits `total` between the entries in proportion to `weights` (evenly if
+// they're all 0), rounding so that the counts add up to `total`
+fn allot(weights: &[f32], total: usize) -> Vec<usize> {
+    let sum: f32 = weights.iter().sum();
+    let shares: Vec<f32> = if sum > 0.0 {
+        weights.iter().map(|w| w / sum * total as f32).collect()
+    } else {
+        vec![total as f32 / weights.len() as f32; weights.len()]
+    };
+
+    let mut counts: Vec<usize> = shares.iter().map(|s| s.floor() as usize).collect();
+    let assigned: usize = counts.iter().sum();
+
+    // The rest goes to the largest remainders
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by(|&a, &b| shares[b].fract().total_cmp(&shares[a].fract()));
+    for &index in order.iter().cycle().take(total.saturating_sub(assigned)) {
+        counts[index] += 1;
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn innovations_of(genome: &Genome) -> Vec<(usize, usize, usize)> {
+        genome.connections.iter().map(|c| (c.innovation, c.from, c.to)).collect()
+    }
+
+    // Only the given term counts towards the distance
+    fn distance_params(excess: f32, disjoint: f32, weight: f32) -> NeatParams {
+        NeatParams {
+            excess_coefficient: excess,
+            disjoint_coefficient: disjoint,
+            weight_coefficient: weight,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn grown_genomes_stay_well_formed() {
+        let params = NeatParams::default();
+        let mut innovations = Innovations::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let mut genome = Genome::new(5, 2, &params, &mut innovations, &mut rng);
+        let mut scratch = Scratch::default();
+
+        for round in 0..200 {
+            if round % 3 == 0 {
+                genome.add_node(&params, &mut innovations, &mut rng);
+            } else {
+                genome.add_connection(&mut innovations, &mut rng);
+            }
+
+            genome.check_shape().unwrap();
+            let outputs = genome.predict_into(&[0.1, 0.9, 0.5, 0.3, 0.7], &mut scratch).unwrap();
+            assert_eq!(outputs.len(), 2);
+            assert!(outputs.iter().all(|o| o.is_finite()));
+        }
+        assert!(genome.hidden_count() >= 60);
+        assert!(genome.enabled_connections() > 10);
+    }
+
+    #[test]
+    fn distance_grows_with_the_genes_only_one_genome_has() {
+        let params = NeatParams::default();
+        let mut innovations = Innovations::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(10);
+        let base = Genome::new(5, 1, &params, &mut innovations, &mut rng);
+        assert_eq!(base.distance(&base.clone(), &params), 0.0);
+
+        // Every split adds two genes the base doesn't have, all past its end
+        let mut grown = base.clone();
+        let mut last = 0.0;
+        for _ in 0..5 {
+            grown.add_node(&params, &mut innovations, &mut rng);
+            let distance = base.distance(&grown, &params);
+            assert!(distance > last, "{} after {}", distance, last);
+            assert_eq!(distance, grown.distance(&base, &params));
+            last = distance;
+        }
+
+        // Two splits of different connections: a's new genes are disjoint
+        // with b's, b's are excess past a's
+        let mut a = base.clone();
+        a.add_node(&params, &mut innovations, &mut rng);
+        let b = loop {
+            let mut b = base.clone();
+            b.add_node(&params, &mut innovations, &mut rng);
+            if innovations_of(&b) != innovations_of(&a) {
+                break b;
+            }
+        };
+        assert_eq!(a.distance(&b, &distance_params(1.0, 0.0, 0.0)), 2.0 / 7.0);
+        assert_eq!(a.distance(&b, &distance_params(0.0, 1.0, 0.0)), 2.0 / 7.0);
+
+        // Shared genes only count by how far apart their weights are
+        let mut heavier = base.clone();
+        heavier.connections[0].weight += 0.5;
+        let distance = base.distance(&heavier, &distance_params(1.0, 1.0, 1.0));
+        assert!((distance - 0.5 / 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn crossover_keeps_the_fitter_parents_topology() {
+        let params = NeatParams::default();
+        let mut innovations = Innovations::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(12);
+        let base = Genome::new(5, 1, &params, &mut innovations, &mut rng);
+        let (mut fitter, mut other) = (base.clone(), base);
+        for _ in 0..6 {
+            fitter.add_node(&params, &mut innovations, &mut rng);
+            fitter.add_connection(&mut innovations, &mut rng);
+        }
+        other.add_node(&params, &mut innovations, &mut rng);
+
+        for (fitter, other) in [(&fitter, &other), (&other, &fitter)] {
+            let child = fitter.crossover(other, &mut rng);
+            child.check_shape().unwrap();
+            assert_eq!(innovations_of(&child), innovations_of(fitter));
+            let ids = |genome: &Genome| genome.nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+            assert_eq!(ids(&child), ids(fitter));
+        }
+    }
+
+    #[test]
+    fn allot_hands_out_exactly_the_total() {
+        let cases: [(&[f32], usize); 7] = [
+            (&[1.0, 2.0, 3.0], 10),
+            (&[1.0, 1.0, 1.0], 10),
+            (&[0.0, 0.0, 0.0], 7),
+            (&[5.0, 0.0], 3),
+            (&[0.1, 100.0, 0.3, 7.7], 1000),
+            (&[2.0], 5),
+            (&[1.0, 2.0], 0),
+        ];
+
+        for (weights, total) in cases {
+            let counts = allot(weights, total);
+            assert_eq!(counts.len(), weights.len());
+            assert_eq!(counts.iter().sum::<usize>(), total, "{:?} of {}", weights, total);
+        }
+        assert_eq!(allot(&[5.0, 0.0], 3), [3, 0]);
+        assert_eq!(allot(&[1.0, 3.0], 8), [2, 6]);
+        // All 0 splits evenly, the remainder one each
+        let even = allot(&[0.0, 0.0, 0.0], 7);
+        assert!(even.iter().all(|&count| count == 2 || count == 3));
+    }
+
+    #[test]
+    fn generations_keep_the_population_size_and_well_formed_genomes() {
+        let params = NeatParams {
+            add_connection_rate: 0.3,
+            add_node_rate: 0.2,
+            ..Default::default()
+        };
+        let mutation = MutationParams::default();
+        let mut neat = Neat::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(14);
+        let mut genomes: Vec<Genome> = (0..40)
+            .map(|_| Genome::new(5, 1, &params, &mut neat.innovations, &mut rng))
+            .collect();
+
+        for _ in 0..15 {
+            let population = genomes
+                .into_iter()
+                .map(|genome| {
+                    let fitness = genome.hidden_count() as f32 + rng.gen_range(0.0..1.0);
+                    (genome, fitness)
+                })
+                .collect();
+            genomes = neat.next_generation(population, &params, &mutation, &mut rng);
+
+            assert_eq!(genomes.len(), 40);
+            assert!(genomes.iter().all(|genome| genome.check_shape().is_ok()));
+            assert_eq!(neat.species().iter().map(|s| s.size).sum::<usize>(), 40);
+        }
+    }
+}
@@ -1,116 +1,1221 @@
+use std::fmt;
+
+use rand::seq::SliceRandom;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// Keeps cross-entropy away from log(0)
+const CROSS_ENTROPY_EPSILON: f32 = 1e-7;
+// Keeps Adam from dividing by 0
+const ADAM_EPSILON: f32 = 1e-8;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Net {
     n_inputs: usize,
     layers: Vec<Layer>,
+    // Evolved by self-adaptive mutation, None until it first mutates the net
+    step_size: Option<f32>,
 }
 
-#[derive(Clone)]
+// A fully connected layer with all its weights in one contiguous buffer
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Layer {
-    nodes: Vec<Vec<f64>>,
+    n_inputs: usize,
+    n_outputs: usize,
+    // `kind.gates()` rows per node, each a bias, a weight per input and for
+    // recurrent kinds a weight per output of the last prediction. Gated nodes
+    // have their update gate row first, then the reset gate, then the node's.
+    weights: Vec<f32>,
+    activation: Activation,
+    kind: LayerKind,
 }
 
-// Config constants
-const BRAIN_MUTATION_RATE: f64 = 0.1;
-const BRAIN_MUTATION_VARIATION: f64 = 0.6;
+// What a layer remembers from one prediction to the next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerKind {
+    // Nothing, the outputs only depend on the inputs
+    #[default]
+    Dense,
+    // Elman: every node also sees the layer's last outputs
+    Recurrent,
+    // GRU: like recurrent, with an update gate deciding how much of the last
+    // outputs to keep and a reset gate how much of them the node sees
+    Gated,
+}
 
-impl Net {
-    pub fn new(layer_sizes: Vec<usize>) -> Self {
-        if layer_sizes.len() < 2 {
-            panic!("Need at least 2 layers");
+impl LayerKind {
+    // Rows of weights per node
+    fn gates(self) -> usize {
+        match self {
+            LayerKind::Dense | LayerKind::Recurrent => 1,
+            LayerKind::Gated => 3,
+        }
+    }
+}
+
+// A net that can't be built, or inputs it can't take
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetError {
+    // Inputs plus at least one layer are needed
+    TooFewLayers(usize),
+    // Layer with no nodes, 0 being the inputs
+    EmptyLayer(usize),
+    // Activations or kinds, one per layer after the inputs
+    WrongLayerCount { what: &'static str, expected: usize, found: usize },
+    WrongInputSize { expected: usize, found: usize },
+    WrongStateSize { expected: usize, found: usize },
+    // Only nets of dense layers can be trained
+    NotDense,
+    // Inputs and targets of a training sample
+    WrongSampleSize { expected: (usize, usize), found: (usize, usize) },
+    // Crossover between nets whose weights don't line up, see `same_shape`
+    DifferentShapes,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::TooFewLayers(found) => write!(f, "Need at least 2 layers, got {}", found),
+            NetError::EmptyLayer(layer) => write!(f, "Layer {} is empty", layer),
+            NetError::WrongLayerCount { what, expected, found } => write!(
+                f,
+                "Need one {} per layer after the inputs ({}), got {}",
+                what, expected, found
+            ),
+            NetError::WrongInputSize { expected, found } => {
+                write!(f, "Net takes {} inputs, got {}", expected, found)
+            }
+            NetError::WrongStateSize { expected, found } => {
+                write!(f, "Net keeps {} values of state, got {}", expected, found)
+            }
+            NetError::NotDense => write!(f, "Only nets of dense layers can be trained"),
+            NetError::WrongSampleSize { expected, found } => write!(
+                f,
+                "Samples need {} inputs and {} targets, got {} and {}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            NetError::DifferentShapes => write!(f, "Crossover needs nets of the same shape"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+// How `Net::new` picks the starting weights of a layer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightInit {
+    // Anywhere within -1..1
+    #[default]
+    Uniform,
+    // Xavier/Glorot: uniform within ±sqrt(6 / (inputs + outputs)), keeps
+    // sigmoid and tanh layers away from saturating
+    Xavier,
+    // He: uniform within ±sqrt(6 / inputs), made for relu layers
+    He,
+}
+
+// See `Config`
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Initialization {
+    pub weights: WeightInit,
+    // Biases start at 0 rather than like the weights
+    pub zero_bias: bool,
+}
+
+// Applied to every node's weighted sum in a layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu,
+    Linear,
+    Step,
+}
+
+impl Activation {
+    // Slope at `y`, given `value` = apply(y). Step counts as flat everywhere,
+    // so gradients don't get through it.
+    fn derivative(self, y: f32, value: f32) -> f32 {
+        match self {
+            Activation::Sigmoid => value * (1.0 - value),
+            Activation::Tanh => 1.0 - value * value,
+            Activation::Relu => if y > 0.0 { 1.0 } else { 0.0 },
+            Activation::LeakyRelu => if y > 0.0 { 1.0 } else { 0.01 },
+            Activation::Linear => 1.0,
+            Activation::Step => 0.0,
+        }
+    }
+
+    pub fn apply(self, y: f32) -> f32 {
+        match self {
+            Activation::Sigmoid => 1f32 / (1f32 + (-y).exp()),
+            Activation::Tanh => y.tanh(),
+            Activation::Relu => y.max(0.0),
+            Activation::LeakyRelu => if y > 0.0 { y } else { 0.01 * y },
+            Activation::Linear => y,
+            Activation::Step => if y > 0.0 { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+// How `Net::crossover` combines two parents
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Crossover {
+    // No recombination, the child is a copy of the first parent
+    None,
+    // Every weight from either parent
+    Uniform,
+    // Every node with all its weights from either parent
+    Neuron,
+    // The first parent's weights up to a random point, the second's after it
+    SinglePoint,
+    // A random mix of both, the same for every weight
+    Blend,
+}
+
+// Buffers for the values passed between layers. Keep one around and hand it to
+// every prediction, once it has grown to the widest layer nothing allocates.
+#[derive(Clone, Debug, Default)]
+pub struct Scratch {
+    pub(crate) current: Vec<f32>,
+    pub(crate) next: Vec<f32>,
+}
+
+// How a changed weight moves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKind {
+    // Up to `variation` either way, all equally likely
+    #[default]
+    Uniform,
+    // Normally distributed with `variation` as the standard deviation
+    Gaussian,
+    // Gaussian with a step size every brain carries and evolves along with its
+    // weights (log-normal, as in evolution strategies), starting at `variation`
+    SelfAdaptive,
+}
+
+// How `variation` shrinks over the generations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anneal {
+    #[default]
+    None,
+    // Down to `anneal_min` of it in a straight line over `anneal_generations`
+    Linear,
+    // Down to `anneal_min` of it over `anneal_generations` too, but shrinking
+    // by the same factor every generation
+    Exponential,
+}
+
+// How `Net::mutate` changes weights, see `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MutationParams {
+    pub kind: MutationKind,
+    // Chance of each weight to change
+    pub rate: f32,
+    // How far a changed weight moves, see `MutationKind`
+    pub variation: f32,
+    // Self-adaptive only: how fast step sizes change, and how small they get
+    pub learning_rate: f32,
+    pub min_step_size: f32,
+    pub anneal: Anneal,
+    pub anneal_generations: u32,
+    // Share of `variation` left at the end of annealing
+    pub anneal_min: f32,
+}
+
+impl Default for MutationParams {
+    fn default() -> Self {
+        Self {
+            kind: MutationKind::Uniform,
+            rate: 0.1,
+            variation: 0.6,
+            learning_rate: 0.2,
+            min_step_size: 0.01,
+            anneal: Anneal::None,
+            anneal_generations: 500,
+            anneal_min: 0.1,
+        }
+    }
+}
+
+impl MutationParams {
+    // These params with `variation` annealed for `generation`, counting from 1
+    pub fn at_generation(&self, generation: u32) -> MutationParams {
+        let elapsed = generation.saturating_sub(1) as f32 / self.anneal_generations.max(1) as f32;
+        let factor = match self.anneal {
+            Anneal::None => 1.0,
+            Anneal::Linear => 1.0 - (1.0 - self.anneal_min) * elapsed.min(1.0),
+            Anneal::Exponential => self.anneal_min.powf(elapsed.min(1.0)),
+        };
+
+        MutationParams {
+            variation: self.variation * factor,
+            ..*self
         }
-        for &size in layer_sizes.iter() {
-            if size < 1 {
-                panic!("Empty layers not allowed");
+    }
+
+    // The scale to mutate a brain's weights with. Self-adaptive mutation
+    // changes the brain's own `step_size` first, so its children inherit it.
+    pub fn step(&self, step_size: &mut Option<f32>, rng: &mut impl Rng) -> f32 {
+        match self.kind {
+            MutationKind::Uniform | MutationKind::Gaussian => self.variation,
+            MutationKind::SelfAdaptive => {
+                let step = step_size.unwrap_or(self.variation)
+                    * (self.learning_rate * standard_normal(rng)).exp();
+                let step = step.max(self.min_step_size);
+                *step_size = Some(step);
+                step
             }
         }
+    }
+
+    // Nudges `value` by up to about `scale` with a chance of `rate`
+    pub fn perturb(&self, value: &mut f32, scale: f32, rng: &mut impl Rng) {
+        if rng.gen_range(0.0..1.0) < self.rate {
+            *value += match self.kind {
+                MutationKind::Uniform => rng.gen_range(-scale..scale),
+                MutationKind::Gaussian | MutationKind::SelfAdaptive => {
+                    scale * standard_normal(rng)
+                }
+            };
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.rate) {
+            return Err(format!("rate must be within 0..1, got {}", self.rate));
+        }
+        if !(self.variation.is_finite() && self.variation > 0.0) {
+            return Err(format!("variation must be positive, got {}", self.variation));
+        }
+        if !(self.learning_rate.is_finite() && self.learning_rate >= 0.0) {
+            return Err(format!(
+                "learning_rate can't be negative, got {}",
+                self.learning_rate
+            ));
+        }
+        if !(self.min_step_size.is_finite() && self.min_step_size > 0.0) {
+            return Err(format!(
+                "min_step_size must be positive, got {}",
+                self.min_step_size
+            ));
+        }
+        if self.anneal != Anneal::None && self.kind == MutationKind::SelfAdaptive {
+            return Err("anneal can't be used with self_adaptive, it tunes its own step sizes".to_string());
+        }
+        if self.anneal_generations == 0 {
+            return Err("anneal_generations must be at least 1".to_string());
+        }
+        if !(self.anneal_min > 0.0 && self.anneal_min <= 1.0) {
+            return Err(format!(
+                "anneal_min must be more than 0 and at most 1, got {}",
+                self.anneal_min
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// What `Net::train` minimizes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loss {
+    // Mean of the squared differences to the targets
+    MeanSquared,
+    // Binary cross-entropy, for outputs and targets within 0..1
+    CrossEntropy,
+}
+
+impl Loss {
+    fn apply(self, output: f32, target: f32) -> f32 {
+        match self {
+            Loss::MeanSquared => (output - target).powi(2),
+            Loss::CrossEntropy => {
+                let output = output.clamp(CROSS_ENTROPY_EPSILON, 1.0 - CROSS_ENTROPY_EPSILON);
+                -(target * output.ln() + (1.0 - target) * (1.0 - output).ln())
+            }
+        }
+    }
+
+    // Slope of `apply` with respect to the output
+    fn derivative(self, output: f32, target: f32) -> f32 {
+        match self {
+            Loss::MeanSquared => 2.0 * (output - target),
+            Loss::CrossEntropy => {
+                let output = output.clamp(CROSS_ENTROPY_EPSILON, 1.0 - CROSS_ENTROPY_EPSILON);
+                (output - target) / (output * (1.0 - output))
+            }
+        }
+    }
+}
+
+// How `Net::train` turns gradients into weight updates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimizer {
+    // Plain gradient descent
+    Sgd,
+    // Gradient descent that keeps some of the last updates going
+    Momentum,
+    Adam,
+}
+
+// Gradient descent knobs for `Net::train`, see `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackpropParams {
+    pub loss: Loss,
+    pub optimizer: Optimizer,
+    pub learning_rate: f32,
+    // Passes over all samples
+    pub epochs: usize,
+    // Samples per weight update
+    pub batch_size: usize,
+    // Share of the last update kept by momentum, Adam's beta1
+    pub momentum: f32,
+    // Adam's beta2
+    pub second_momentum: f32,
+}
+
+impl Default for BackpropParams {
+    fn default() -> Self {
+        Self {
+            loss: Loss::CrossEntropy,
+            optimizer: Optimizer::Adam,
+            learning_rate: 0.001,
+            epochs: 20,
+            batch_size: 32,
+            momentum: 0.9,
+            second_momentum: 0.999,
+        }
+    }
+}
+
+impl BackpropParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.learning_rate.is_finite() && self.learning_rate > 0.0) {
+            return Err(format!(
+                "learning_rate must be positive, got {}",
+                self.learning_rate
+            ));
+        }
+        if self.epochs == 0 {
+            return Err("epochs must be at least 1".to_string());
+        }
+        if self.batch_size == 0 {
+            return Err("batch_size must be at least 1".to_string());
+        }
+        let momenta = [
+            ("momentum", self.momentum),
+            ("second_momentum", self.second_momentum),
+        ];
+        for (name, value) in momenta {
+            if !(0.0..1.0).contains(&value) {
+                return Err(format!("{} must be within 0..1 (exclusive), got {}", name, value));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Box-Muller, a sample of the normal distribution with mean 0 and deviation 1
+fn standard_normal(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = 1.0 - rng.gen_range(0.0..1.0f32);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+impl Net {
+    // One activation and kind per layer after the inputs
+    pub fn new(
+        layer_sizes: Vec<usize>,
+        activations: Vec<Activation>,
+        kinds: Vec<LayerKind>,
+        init: &Initialization,
+        rng: &mut impl Rng,
+    ) -> Result<Self, NetError> {
+        Self::check_layout(&layer_sizes, &activations, &kinds)?;
 
         let mut layers = Vec::new();
         let first_layer_size = *layer_sizes.first().unwrap();
         let mut prev_layer_size = first_layer_size;
 
-        for &layer_size in layer_sizes[1..].iter() {
-            layers.push(Layer::new(layer_size, prev_layer_size));
+        for ((&layer_size, activation), kind) in layer_sizes[1..].iter().zip(activations).zip(kinds) {
+            layers.push(Layer::new(layer_size, prev_layer_size, activation, kind, init, rng));
             prev_layer_size = layer_size;
         }
 
-        Self {
+        Ok(Self {
             layers,
             n_inputs: first_layer_size,
+            step_size: None,
+        })
+    }
+
+    // Whether `Net::new` can build a net from these
+    pub fn check_layout(
+        layer_sizes: &[usize],
+        activations: &[Activation],
+        kinds: &[LayerKind],
+    ) -> Result<(), NetError> {
+        if layer_sizes.len() < 2 {
+            return Err(NetError::TooFewLayers(layer_sizes.len()));
+        }
+        if let Some(layer) = layer_sizes.iter().position(|&size| size == 0) {
+            return Err(NetError::EmptyLayer(layer));
         }
+
+        let expected = layer_sizes.len() - 1;
+        for (what, found) in [("activation", activations.len()), ("kind", kinds.len())] {
+            if found != expected {
+                return Err(NetError::WrongLayerCount { what, expected, found });
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn predict(&self, inputs: &Vec<f64>) -> Vec<f64> {
+    // From a blank state, allocates the result. Use `predict_into` in hot
+    // loops and to carry the state of recurrent layers over.
+    pub fn predict(&self, inputs: &[f32]) -> Result<Vec<f32>, NetError> {
+        let mut state = vec![0.0; self.state_size()];
+        Ok(self.predict_into(inputs, &mut state, &mut Scratch::default())?.to_vec())
+    }
+
+    // Runs the net using `scratch` for the layer values, the outputs are
+    // returned from it. `state` holds the last outputs of the recurrent layers
+    // (all zeros to start with) and is updated with the new ones.
+    pub fn predict_into<'s>(
+        &self,
+        inputs: &[f32],
+        state: &mut [f32],
+        scratch: &'s mut Scratch,
+    ) -> Result<&'s [f32], NetError> {
         if inputs.len() != self.n_inputs {
-            panic!("Bad input size");
+            return Err(NetError::WrongInputSize {
+                expected: self.n_inputs,
+                found: inputs.len(),
+            });
+        }
+        if state.len() != self.state_size() {
+            return Err(NetError::WrongStateSize {
+                expected: self.state_size(),
+                found: state.len(),
+            });
         }
 
-        let mut outputs = Vec::new();
-        outputs.push(inputs.clone());
-        for (layer_index, layer) in self.layers.iter().enumerate() {
-            let layer_results = layer.predict(&outputs[layer_index]);
-            outputs.push(layer_results);
+        scratch.current.clear();
+        scratch.current.extend_from_slice(inputs);
+        let mut offset = 0;
+        for layer in self.layers.iter() {
+            let layer_state = &mut state[offset..offset + layer.state_size()];
+            offset += layer_state.len();
+
+            scratch.next.resize(layer.n_outputs, 0.0);
+            layer.predict(&scratch.current, layer_state, &mut scratch.next);
+            layer_state.copy_from_slice(&scratch.next[..layer_state.len()]);
+            std::mem::swap(&mut scratch.current, &mut scratch.next);
+        }
+
+        Ok(&scratch.current)
+    }
+
+    // The values of every layer, inputs first, for one prediction from
+    // `state`, which is left as it is. A state that doesn't fit counts as
+    // blank.
+    pub fn trace(&self, inputs: &[f32], state: &[f32]) -> Vec<Vec<f32>> {
+        let mut state = if state.len() == self.state_size() {
+            state.to_vec()
+        } else {
+            vec![0.0; self.state_size()]
+        };
+
+        let mut values = vec![inputs.to_vec()];
+        let mut offset = 0;
+        for layer in self.layers.iter() {
+            let layer_state = &mut state[offset..offset + layer.state_size()];
+            offset += layer_state.len();
+
+            let mut outputs = vec![0.0; layer.n_outputs];
+            layer.predict(values.last().unwrap(), layer_state, &mut outputs);
+            values.push(outputs);
+        }
+
+        values
+    }
+
+    // Weights from the layer before into `node` of layer `layer` (0 is the
+    // first after the inputs), without bias or recurrent weights. For gated
+    // layers those of the node's own row, not the gates'.
+    pub fn node_weights(&self, layer: usize, node: usize) -> &[f32] {
+        let layer = &self.layers[layer];
+        let start = (node + 1) * layer.node_len() - layer.row_len() + 1;
+        &layer.weights[start..start + layer.n_inputs]
+    }
+
+    // Gradient descent on `samples` of (inputs, target outputs), shuffled
+    // anew for every epoch. Returns the mean loss of each epoch. Only works on
+    // nets of dense layers, there's no backpropagation through time.
+    pub fn train(
+        &mut self,
+        samples: &[(Vec<f32>, Vec<f32>)],
+        params: &BackpropParams,
+        rng: &mut impl Rng,
+    ) -> Result<Vec<f32>, NetError> {
+        if self.layers.iter().any(|l| l.kind != LayerKind::Dense) {
+            return Err(NetError::NotDense);
+        }
+        let expected = (self.n_inputs, self.n_outputs());
+        if let Some((inputs, targets)) = samples
+            .iter()
+            .find(|(inputs, targets)| (inputs.len(), targets.len()) != expected)
+        {
+            return Err(NetError::WrongSampleSize { expected, found: (inputs.len(), targets.len()) });
+        }
+
+        let zeros = |net: &Net| -> Vec<Vec<f32>> {
+            net.layers.iter().map(|l| vec![0.0; l.weights.len()]).collect()
+        };
+        let mut gradients = zeros(self);
+        // Running averages of the gradients and their squares
+        let mut first_moments = zeros(self);
+        let mut second_moments = zeros(self);
+        let mut updates = 0;
+
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        let mut epoch_losses = Vec::with_capacity(params.epochs);
+        for _ in 0..params.epochs {
+            order.shuffle(rng);
+            let mut total_loss = 0.0;
+
+            for batch in order.chunks(params.batch_size) {
+                gradients.iter_mut().for_each(|g| g.fill(0.0));
+                for &index in batch {
+                    let (inputs, targets) = &samples[index];
+                    total_loss += self.backpropagate(inputs, targets, params.loss, &mut gradients);
+                }
+
+                updates += 1;
+                let scale = 1.0 / batch.len() as f32;
+                let layers = self.layers.iter_mut().zip(gradients.iter());
+                let moments = first_moments.iter_mut().zip(second_moments.iter_mut());
+                for ((layer, gradients), (first, second)) in layers.zip(moments) {
+                    for (i, weight) in layer.weights.iter_mut().enumerate() {
+                        let gradient = gradients[i] * scale;
+                        *weight -= params.learning_rate
+                            * match params.optimizer {
+                                Optimizer::Sgd => gradient,
+                                Optimizer::Momentum => {
+                                    first[i] = params.momentum * first[i] + gradient;
+                                    first[i]
+                                }
+                                Optimizer::Adam => {
+                                    let (beta1, beta2) = (params.momentum, params.second_momentum);
+                                    first[i] = beta1 * first[i] + (1.0 - beta1) * gradient;
+                                    second[i] = beta2 * second[i] + (1.0 - beta2) * gradient * gradient;
+                                    let first = first[i] / (1.0 - beta1.powi(updates));
+                                    let second = second[i] / (1.0 - beta2.powi(updates));
+                                    first / (second.sqrt() + ADAM_EPSILON)
+                                }
+                            };
+                    }
+                }
+            }
+
+            epoch_losses.push(total_loss / samples.len().max(1) as f32);
+        }
+
+        Ok(epoch_losses)
+    }
+
+    // Adds the gradients of the loss for one sample to `gradients` (laid out
+    // like the weights) and returns the loss
+    fn backpropagate(
+        &self,
+        inputs: &[f32],
+        targets: &[f32],
+        loss: Loss,
+        gradients: &mut [Vec<f32>],
+    ) -> f32 {
+        // Weighted sums and activations of every layer, inputs first
+        let mut sums = Vec::with_capacity(self.layers.len());
+        let mut values = vec![inputs.to_vec()];
+        for layer in self.layers.iter() {
+            let previous = values.last().unwrap();
+            let layer_sums: Vec<f32> = layer
+                .weights
+                .chunks_exact(layer.row_len())
+                .map(|row| layer.dot_prod(row, previous, &[]))
+                .collect();
+            values.push(layer_sums.iter().map(|&y| layer.activation.apply(y)).collect());
+            sums.push(layer_sums);
+        }
+
+        let outputs = values.last().unwrap();
+        let n_outputs = outputs.len() as f32;
+        let total_loss = outputs
+            .iter()
+            .zip(targets)
+            .map(|(&output, &target)| loss.apply(output, target))
+            .sum::<f32>()
+            / n_outputs;
+        // Slopes of the loss with respect to the values of the current layer
+        let mut slopes: Vec<f32> = outputs
+            .iter()
+            .zip(targets)
+            .map(|(&output, &target)| loss.derivative(output, target) / n_outputs)
+            .collect();
+
+        for (index, layer) in self.layers.iter().enumerate().rev() {
+            let (previous, current) = (&values[index], &values[index + 1]);
+            let mut previous_slopes = vec![0.0; layer.n_inputs];
+
+            let rows = layer.weights.chunks_exact(layer.row_len());
+            let gradient_rows = gradients[index].chunks_exact_mut(layer.row_len());
+            for (node, (row, gradient_row)) in rows.zip(gradient_rows).enumerate() {
+                let delta = slopes[node] * layer.activation.derivative(sums[index][node], current[node]);
+                let (bias_gradient, weight_gradients) = gradient_row.split_first_mut().unwrap();
+                *bias_gradient += delta;
+
+                let weights = row[1..].iter().zip(weight_gradients.iter_mut());
+                let inputs = previous.iter().zip(previous_slopes.iter_mut());
+                for ((&weight, gradient), (&value, slope)) in weights.zip(inputs) {
+                    *gradient += delta * value;
+                    *slope += delta * weight;
+                }
+            }
+
+            slopes = previous_slopes;
+        }
+
+        total_loss
+    }
+
+    pub fn mutate(&mut self, params: &MutationParams, rng: &mut impl Rng) {
+        let scale = params.step(&mut self.step_size, rng);
+        self.layers.iter_mut().for_each(|l| l.mutate(params, scale, rng));
+    }
+
+    pub fn step_size(&self) -> Option<f32> {
+        self.step_size
+    }
+
+    // A child of this net and `other`, which needs the same shape (see
+    // `same_shape`)
+    pub fn crossover(&self, other: &Net, method: Crossover, rng: &mut impl Rng) -> Result<Net, NetError> {
+        if !self.same_shape(other) {
+            return Err(NetError::DifferentShapes);
+        }
+
+        let mut child = self.clone();
+        match method {
+            Crossover::None => {}
+            Crossover::Uniform => {
+                for (weight, &theirs) in child.weights_mut().zip(other.weights()) {
+                    if rng.gen_bool(0.5) {
+                        *weight = theirs;
+                    }
+                }
+            }
+            Crossover::Neuron => {
+                for (layer, theirs) in child.layers.iter_mut().zip(other.layers.iter()) {
+                    let node_len = layer.node_len();
+                    let nodes = layer.weights.chunks_exact_mut(node_len);
+                    for (node, their_node) in nodes.zip(theirs.weights.chunks_exact(node_len)) {
+                        if rng.gen_bool(0.5) {
+                            node.copy_from_slice(their_node);
+                        }
+                    }
+                }
+            }
+            Crossover::SinglePoint => {
+                let point = rng.gen_range(0..=self.weights().count());
+                for (weight, &theirs) in child.weights_mut().zip(other.weights()).skip(point) {
+                    *weight = theirs;
+                }
+            }
+            Crossover::Blend => {
+                let mix: f32 = rng.gen_range(0.0..1.0);
+                for (weight, &theirs) in child.weights_mut().zip(other.weights()) {
+                    *weight = mix * *weight + (1.0 - mix) * theirs;
+                }
+            }
         }
 
-        outputs.pop().unwrap()
+        Ok(child)
+    }
+
+    // Same layer sizes, activations and kinds, so weights line up one to one
+    pub fn same_shape(&self, other: &Net) -> bool {
+        self.n_inputs == other.n_inputs
+            && self.layers.len() == other.layers.len()
+            && self.layers.iter().zip(other.layers.iter()).all(|(a, b)| {
+                a.n_inputs == b.n_inputs
+                    && a.n_outputs == b.n_outputs
+                    && a.activation == b.activation
+                    && a.kind == b.kind
+            })
+    }
+
+    fn weights(&self) -> impl Iterator<Item = &f32> {
+        self.layers.iter().flat_map(|l| l.weights.iter())
+    }
+
+    fn weights_mut(&mut self) -> impl Iterator<Item = &mut f32> {
+        self.layers.iter_mut().flat_map(|l| l.weights.iter_mut())
+    }
+
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    // Sizes as passed to Net::new, inputs first
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        std::iter::once(self.n_inputs)
+            .chain(self.layers.iter().map(|l| l.n_outputs))
+            .collect()
     }
 
-    pub fn mutate(&mut self) {
-        self.layers.iter_mut().for_each(|l| l.mutate());
+    // All weights of layer `layer` (0 is the first after the inputs) in the
+    // order they're stored, see `Layer`
+    pub fn layer_weights(&self, layer: usize) -> &[f32] {
+        &self.layers[layer].weights
+    }
+
+    pub fn activations(&self) -> Vec<Activation> {
+        self.layers.iter().map(|l| l.activation).collect()
+    }
+
+    pub fn kinds(&self) -> Vec<LayerKind> {
+        self.layers.iter().map(|l| l.kind).collect()
+    }
+
+    // Values `predict_into` keeps between predictions
+    pub fn state_size(&self) -> usize {
+        self.layers.iter().map(|l| l.state_size()).sum()
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        self.layers.last().map_or(0, |l| l.n_outputs)
+    }
+
+    // Nets from Net::new are always well formed, but one read from disk may
+    // not be: every layer takes the outputs of the one before, and has a bias
+    // plus one weight per input (and per output if recurrent) for each row of
+    // each of its nodes
+    pub fn check_shape(&self) -> Result<(), String> {
+        if self.n_inputs < 1 || self.layers.is_empty() {
+            return Err("Need at least 2 layers".to_string());
+        }
+
+        let mut prev_layer_size = self.n_inputs;
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            if layer.n_outputs == 0 {
+                return Err(format!("Layer {} is empty", layer_index + 1));
+            }
+            if layer.n_inputs != prev_layer_size {
+                return Err(format!(
+                    "Layer {} takes {} inputs, the layer before has {} nodes",
+                    layer_index + 1,
+                    layer.n_inputs,
+                    prev_layer_size
+                ));
+            }
+            let expected = layer.n_outputs * layer.node_len();
+            if layer.weights.len() != expected {
+                return Err(format!(
+                    "Layer {} has {} weights, expected {}",
+                    layer_index + 1,
+                    layer.weights.len(),
+                    expected
+                ));
+            }
+            prev_layer_size = layer.n_outputs;
+        }
+
+        Ok(())
     }
 }
 
 impl Layer {
-    fn new(layer_size: usize, prev_layer_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut nodes: Vec<Vec<f64>> = Vec::new();
+    fn new(
+        layer_size: usize,
+        prev_layer_size: usize,
+        activation: Activation,
+        kind: LayerKind,
+        init: &Initialization,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let mut layer = Self {
+            n_inputs: prev_layer_size,
+            n_outputs: layer_size,
+            weights: Vec::new(),
+            activation,
+            kind,
+        };
 
-        for _ in 0..layer_size {
-            let mut node: Vec<f64> = Vec::new();
-            for _ in 0..prev_layer_size + 1 {
-                let random_weight: f64 = rng.gen_range(-1.0..1.0);
-                node.push(random_weight);
-            }
-            nodes.push(node);
+        // Recurrent nodes also take the last outputs as inputs
+        let fan_in = (layer.row_len() - 1) as f32;
+        let limit = match init.weights {
+            WeightInit::Uniform => 1.0,
+            WeightInit::Xavier => (6.0 / (fan_in + layer_size as f32)).sqrt(),
+            WeightInit::He => (6.0 / fan_in).sqrt(),
+        };
+        let row_len = layer.row_len();
+        layer.weights = (0..layer_size * layer.node_len())
+            .map(|i| {
+                if init.zero_bias && i % row_len == 0 {
+                    0.0
+                } else {
+                    rng.gen_range(-limit..limit)
+                }
+            })
+            .collect();
+
+        layer
+    }
+
+    // Bias, weights for the inputs and for recurrent kinds the last outputs
+    fn row_len(&self) -> usize {
+        1 + self.n_inputs + self.state_size()
+    }
+
+    fn node_len(&self) -> usize {
+        self.kind.gates() * self.row_len()
+    }
+
+    fn state_size(&self) -> usize {
+        match self.kind {
+            LayerKind::Dense => 0,
+            LayerKind::Recurrent | LayerKind::Gated => self.n_outputs,
         }
+    }
 
-        Self { nodes }
+    // `state` holds the last outputs for recurrent kinds and is empty otherwise
+    fn predict(&self, inputs: &[f32], state: &[f32], outputs: &mut [f32]) {
+        let nodes = self.weights.chunks_exact(self.node_len());
+        for (j, (node, output)) in nodes.zip(outputs.iter_mut()).enumerate() {
+            *output = match self.kind {
+                LayerKind::Dense | LayerKind::Recurrent => {
+                    self.activation.apply(self.dot_prod(node, inputs, state))
+                }
+                LayerKind::Gated => {
+                    let (gates, row) = node.split_at(2 * self.row_len());
+                    let (update, reset) = gates.split_at(self.row_len());
+                    let update = Activation::Sigmoid.apply(self.dot_prod(update, inputs, state));
+                    let reset = Activation::Sigmoid.apply(self.dot_prod(reset, inputs, state));
+
+                    // The reset gate scales what the node takes from the last outputs
+                    let (row, recurrent) = row.split_at(1 + self.n_inputs);
+                    let recurrent: f32 = recurrent.iter().zip(state).map(|(w, h)| w * h).sum();
+                    let candidate = self
+                        .activation
+                        .apply(self.dot_prod(row, inputs, &[]) + reset * recurrent);
+                    (1.0 - update) * state[j] + update * candidate
+                }
+            };
+        }
     }
 
-    fn predict(&self, inputs: &Vec<f64>) -> Vec<f64> {
-        let mut layer_results = Vec::new();
-        for node in self.nodes.iter() {
-            layer_results.push(self.sigmoid(self.dot_prod(&node, &inputs)));
+    fn mutate(&mut self, params: &MutationParams, scale: f32, rng: &mut impl Rng) {
+        for val in self.weights.iter_mut() {
+            params.perturb(val, scale, rng);
         }
+    }
+
+    // `row` is a bias followed by one weight per input, then one per value of
+    // `state`
+    fn dot_prod(&self, row: &[f32], inputs: &[f32], state: &[f32]) -> f32 {
+        let (bias, weights) = row.split_first().unwrap();
+        weights
+            .iter()
+            .zip(inputs.iter().chain(state.iter()))
+            .fold(*bias, |total, (weight, value)| total + weight * value)
+    }
+}
 
-        layer_results
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::brain::Brain;
+    use crate::brain_file;
+
+    use super::*;
+
+    fn dense(layer_sizes: Vec<usize>, activations: Vec<Activation>, rng: &mut ChaCha8Rng) -> Net {
+        let kinds = vec![LayerKind::Dense; activations.len()];
+        Net::new(layer_sizes, activations, kinds, &Initialization::default(), rng).unwrap()
     }
 
-    fn mutate(&mut self) {
-        let mut rng = rand::thread_rng();
-        for n in self.nodes.iter_mut() {
-            for val in n.iter_mut() {
-                if rng.gen_range(0.0..1.0) >= BRAIN_MUTATION_RATE {
+    #[test]
+    fn backpropagated_gradients_match_finite_differences() {
+        const H: f32 = 1e-3;
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let inputs = [0.3, -0.7, 0.9];
+        let targets = [0.2, 0.8];
+
+        for activations in [
+            vec![Activation::Tanh, Activation::Sigmoid],
+            vec![Activation::Sigmoid, Activation::Linear],
+        ] {
+            for loss in [Loss::MeanSquared, Loss::CrossEntropy] {
+                if loss == Loss::CrossEntropy && activations[1] == Activation::Linear {
                     continue;
                 }
+                let mut net = dense(vec![3, 4, 2], activations.clone(), &mut rng);
+                let mut gradients: Vec<Vec<f32>> =
+                    net.layers.iter().map(|l| vec![0.0; l.weights.len()]).collect();
+                net.backpropagate(&inputs, &targets, loss, &mut gradients);
 
-                *val += rng.gen_range(-BRAIN_MUTATION_VARIATION..BRAIN_MUTATION_VARIATION);
+                let mut scratch = gradients.clone();
+                for (layer, layer_gradients) in gradients.iter().enumerate() {
+                    for (i, &gradient) in layer_gradients.iter().enumerate() {
+                        let weight = net.layers[layer].weights[i];
+                        net.layers[layer].weights[i] = weight + H;
+                        let above = net.backpropagate(&inputs, &targets, loss, &mut scratch);
+                        net.layers[layer].weights[i] = weight - H;
+                        let below = net.backpropagate(&inputs, &targets, loss, &mut scratch);
+                        net.layers[layer].weights[i] = weight;
+
+                        let numeric = (above - below) / (2.0 * H);
+                        assert!(
+                            (numeric - gradient).abs() <= 1e-3 + 1e-2 * gradient.abs(),
+                            "{:?} {:?}, layer {} weight {}: backprop {} vs numeric {}",
+                            activations,
+                            loss,
+                            layer,
+                            i,
+                            gradient,
+                            numeric
+                        );
+                    }
+                }
             }
         }
     }
 
-    fn dot_prod(&self, node: &Vec<f64>, values: &Vec<f64>) -> f64 {
-        let mut it = node.iter();
-        let mut total = *it.next().unwrap();
-        for (weight, value) in it.zip(values.iter()) {
-            total += weight * value;
+    #[test]
+    fn training_lowers_the_loss_with_every_optimizer() {
+        // The target is just the first input, the second is noise
+        let mut rng = ChaCha8Rng::seed_from_u64(8);
+        let samples: Vec<_> = (0..32)
+            .map(|i| {
+                let first = (i % 2) as f32;
+                (vec![first, rng.gen_range(-1.0..1.0)], vec![first])
+            })
+            .collect();
+
+        for (optimizer, learning_rate) in [
+            (Optimizer::Sgd, 0.5),
+            (Optimizer::Momentum, 0.1),
+            (Optimizer::Adam, 0.05),
+        ] {
+            let mut net = dense(vec![2, 4, 1], vec![Activation::Tanh, Activation::Sigmoid], &mut rng);
+            let params = BackpropParams {
+                optimizer,
+                learning_rate,
+                epochs: 50,
+                batch_size: 4,
+                ..Default::default()
+            };
+            let losses = net.train(&samples, &params, &mut rng).unwrap();
+
+            let (first, last) = (losses[0], *losses.last().unwrap());
+            assert!(last < first * 0.5, "{:?}: loss went from {} to {}", optimizer, first, last);
+            assert!(net.predict(&[1.0, 0.0]).unwrap()[0] > 0.5);
+            assert!(net.predict(&[0.0, 0.0]).unwrap()[0] < 0.5);
         }
+    }
+
+    // A recurrent layer, then a gated one, then a dense output
+    fn recurrent(rng: &mut ChaCha8Rng) -> Net {
+        let activations = vec![Activation::Tanh, Activation::Tanh, Activation::Sigmoid];
+        let kinds = vec![LayerKind::Recurrent, LayerKind::Gated, LayerKind::Dense];
+        Net::new(vec![5, 4, 3, 1], activations, kinds, &Initialization::default(), rng).unwrap()
+    }
 
-        total
+    // Outputs of `brain` for the same inputs on `ticks` ticks in a row
+    fn outputs_over_ticks(brain: &Brain, memory: &mut Vec<f32>, ticks: usize) -> Vec<f32> {
+        let mut scratch = Scratch::default();
+        (0..ticks)
+            .map(|_| brain.predict_into(&[0.2, 0.8, 0.5, 0.1, 0.6], memory, &mut scratch).unwrap()[0])
+            .collect()
     }
 
-    fn sigmoid(&self, y: f64) -> f64 {
-        1f64 / (1f64 + (-y).exp())
+    #[test]
+    fn recurrent_nets_remember_until_their_memory_is_cleared() {
+        let mut rng = ChaCha8Rng::seed_from_u64(9);
+        let brain = Brain::Net(recurrent(&mut rng));
+        let mut memory = Vec::new();
+
+        let outputs = outputs_over_ticks(&brain, &mut memory, 3);
+        assert_eq!(memory.len(), 4 + 3);
+        assert_ne!(outputs[0], outputs[1]);
+        assert_ne!(outputs[1], outputs[2]);
+
+        // As on respawn
+        memory.clear();
+        assert_eq!(outputs_over_ticks(&brain, &mut memory, 3), outputs);
+
+        // Dense nets have nothing to remember
+        let dense = Brain::Net(dense(vec![5, 4, 1], vec![Activation::Tanh, Activation::Sigmoid], &mut rng));
+        let outputs = outputs_over_ticks(&dense, &mut memory, 2);
+        assert!(memory.is_empty());
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn recurrent_and_gate_weights_survive_mutation_crossover_and_files() {
+        let mut rng = ChaCha8Rng::seed_from_u64(11);
+        let parent = recurrent(&mut rng);
+        let other = recurrent(&mut rng);
+        // Bias, inputs and the layer's own last outputs per row, three rows
+        // per gated node
+        let expected_weights = [4 * (1 + 5 + 4), 3 * 3 * (1 + 4 + 3), 1 + 3];
+        let weights = |net: &Net| (0..3).map(|layer| net.layer_weights(layer).len()).collect::<Vec<_>>();
+        assert_eq!(weights(&parent), expected_weights);
+
+        let mut mutated = parent.clone();
+        mutated.mutate(&MutationParams { rate: 1.0, ..Default::default() }, &mut rng);
+        assert!(mutated.same_shape(&parent));
+        assert_eq!(weights(&mutated), expected_weights);
+        assert_ne!(mutated.layer_weights(1), parent.layer_weights(1));
+
+        for method in [Crossover::Uniform, Crossover::Neuron, Crossover::SinglePoint] {
+            let child = parent.crossover(&other, method, &mut rng).unwrap();
+            assert_eq!(child.kinds(), parent.kinds());
+            for layer in 0..3 {
+                let parents = parent.layer_weights(layer).iter().zip(other.layer_weights(layer));
+                for (weight, (ours, theirs)) in child.layer_weights(layer).iter().zip(parents) {
+                    assert!(weight == ours || weight == theirs);
+                }
+            }
+        }
+
+        let brain = Brain::Net(parent);
+        let from_json = brain_file::from_json(&brain_file::to_json(&brain)).unwrap();
+        let from_bytes = brain_file::from_bytes(&brain_file::to_bytes(&brain)).unwrap();
+        let expected = outputs_over_ticks(&brain, &mut Vec::new(), 4);
+        for loaded in [from_json, from_bytes] {
+            let Brain::Net(net) = &loaded else {
+                panic!("Loaded {} instead of a net", loaded);
+            };
+            assert_eq!(net.kinds(), [LayerKind::Recurrent, LayerKind::Gated, LayerKind::Dense]);
+            assert_eq!(outputs_over_ticks(&loaded, &mut Vec::new(), 4), expected);
+        }
+    }
+
+    #[test]
+    fn crossover_needs_parents_of_the_same_shape() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let parent = dense(vec![5, 8, 1], vec![Activation::Tanh, Activation::Sigmoid], &mut rng);
+        let other = dense(vec![5, 8, 1], vec![Activation::Tanh, Activation::Sigmoid], &mut rng);
+        let loaded = dense(vec![5, 4, 1], vec![Activation::Tanh, Activation::Sigmoid], &mut rng);
+
+        let child = parent.crossover(&other, Crossover::Uniform, &mut rng).unwrap();
+        assert!(child.same_shape(&parent));
+        for method in [Crossover::Uniform, Crossover::Neuron, Crossover::SinglePoint, Crossover::Blend] {
+            assert_eq!(
+                parent.crossover(&loaded, method, &mut rng).map(|_| ()),
+                Err(NetError::DifferentShapes)
+            );
+        }
+    }
+
+    #[test]
+    fn training_rejects_recurrent_nets_and_misshapen_samples() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let params = BackpropParams::default();
+
+        let mut net = dense(vec![2, 1], vec![Activation::Sigmoid], &mut rng);
+        let samples = vec![(vec![0.0; 3], vec![1.0])];
+        assert_eq!(
+            net.train(&samples, &params, &mut rng),
+            Err(NetError::WrongSampleSize { expected: (2, 1), found: (3, 1) })
+        );
+
+        let kinds = vec![LayerKind::Recurrent];
+        let mut net =
+            Net::new(vec![2, 1], vec![Activation::Sigmoid], kinds, &Initialization::default(), &mut rng).unwrap();
+        assert_eq!(net.train(&[], &params, &mut rng), Err(NetError::NotDense));
+    }
+
+    #[test]
+    fn annealing_reaches_anneal_min_at_anneal_generations() {
+        for anneal in [Anneal::Linear, Anneal::Exponential] {
+            let params = MutationParams {
+                anneal,
+                variation: 0.8,
+                anneal_generations: 100,
+                anneal_min: 0.25,
+                ..Default::default()
+            };
+            let variation = |generation| params.at_generation(generation).variation;
+
+            assert_eq!(variation(1), 0.8);
+            let halfway = variation(51);
+            assert!(0.2 < halfway && halfway < 0.8, "{:?}: {}", anneal, halfway);
+            assert!((variation(101) - 0.2).abs() < 1e-6, "{:?}: {}", anneal, variation(101));
+            assert!((variation(1000) - 0.2).abs() < 1e-6, "{:?}: {}", anneal, variation(1000));
+        }
+
+        let constant = MutationParams::default();
+        assert_eq!(constant.at_generation(1000).variation, constant.variation);
+    }
+
+    #[test]
+    fn self_adaptive_steps_never_go_below_min_step_size() {
+        let mut rng = ChaCha8Rng::seed_from_u64(8);
+        let params = MutationParams {
+            kind: MutationKind::SelfAdaptive,
+            variation: 0.1,
+            learning_rate: 2.0,
+            min_step_size: 0.05,
+            ..Default::default()
+        };
+
+        let mut step_size = None;
+        let steps: Vec<f32> = (0..1000).map(|_| params.step(&mut step_size, &mut rng)).collect();
+        assert!(steps.iter().all(|&step| step >= 0.05));
+        assert!(steps.contains(&0.05));
+        assert_eq!(step_size, steps.last().copied());
+    }
+
+    #[test]
+    fn children_inherit_the_step_size() {
+        let mut rng = ChaCha8Rng::seed_from_u64(9);
+        let params = MutationParams {
+            kind: MutationKind::SelfAdaptive,
+            ..Default::default()
+        };
+        let mut parent = dense(vec![2, 3, 1], vec![Activation::Tanh; 2], &mut rng);
+        let other = dense(vec![2, 3, 1], vec![Activation::Tanh; 2], &mut rng);
+        assert_eq!(parent.step_size(), None);
+        parent.mutate(&params, &mut rng);
+        let step_size = parent.step_size().unwrap();
+
+        let mut child = parent.crossover(&other, Crossover::Uniform, &mut rng).unwrap();
+        assert_eq!(child.step_size(), Some(step_size));
+
+        // Without a learning rate the inherited step size is kept as is
+        let params = MutationParams {
+            learning_rate: 0.0,
+            ..params
+        };
+        child.mutate(&params, &mut rng);
+        assert_eq!(child.step_size(), Some(step_size));
     }
 }
@@ -0,0 +1,306 @@
+// ONNX export, so trained nets can run outside the game. Every dense layer
+// becomes a Gemm (weights as [outputs, inputs] with transB, biases on their
+// own) followed by its activation. The model takes "input" of shape
+// [N, inputs] and gives "output" of shape [N, outputs].
+// ONNX files are protobuf, the few messages needed are encoded by hand below.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::nn::{Activation, LayerKind, Net};
+
+// Opset 13 needs IR version 7
+const ONNX_IR_VERSION: i64 = 7;
+const ONNX_OPSET_VERSION: i64 = 13;
+
+// TensorProto.DataType
+const FLOAT: i64 = 1;
+// AttributeProto.AttributeType
+const ATTRIBUTE_FLOAT: i64 = 1;
+const ATTRIBUTE_INT: i64 = 2;
+
+const INPUT_NAME: &str = "input";
+const OUTPUT_NAME: &str = "output";
+
+#[derive(Debug)]
+pub enum OnnxError {
+    Io(io::Error),
+    // Recurrent layers have no plain ONNX counterpart yet
+    Unsupported(String),
+}
+
+impl fmt::Display for OnnxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OnnxError::Io(err) => write!(f, "{}", err),
+            OnnxError::Unsupported(reason) => write!(f, "Can't export to ONNX: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OnnxError {}
+
+impl From<io::Error> for OnnxError {
+    fn from(err: io::Error) -> Self {
+        OnnxError::Io(err)
+    }
+}
+
+// A protobuf message being written, fields in any order
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    // Wire types: 0 varint, 2 length delimited, 5 32 bit
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint((field << 3) | wire_type);
+    }
+
+    fn int(&mut self, field: u64, value: i64) -> &mut Self {
+        self.key(field, 0);
+        self.varint(value as u64);
+        self
+    }
+
+    fn float(&mut self, field: u64, value: f32) -> &mut Self {
+        self.key(field, 5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) -> &mut Self {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn string(&mut self, field: u64, value: &str) -> &mut Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(&mut self, field: u64, message: &Message) -> &mut Self {
+        self.bytes(field, &message.0)
+    }
+}
+
+// TensorProto of f32 `values`
+fn tensor(name: &str, dims: &[usize], values: &[f32]) -> Message {
+    let mut tensor = Message::default();
+    for &dim in dims {
+        tensor.int(1, dim as i64);
+    }
+    tensor.int(2, FLOAT).string(8, name);
+    let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    tensor.bytes(9, &raw);
+    tensor
+}
+
+// ValueInfoProto of a float tensor with a batch dimension of any size first
+fn batch_value(name: &str, width: usize) -> Message {
+    let mut batch = Message::default();
+    batch.string(2, "N");
+    let mut columns = Message::default();
+    columns.int(1, width as i64);
+    let mut shape = Message::default();
+    shape.message(1, &batch).message(1, &columns);
+
+    let mut tensor_type = Message::default();
+    tensor_type.int(1, FLOAT).message(2, &shape);
+    let mut value_type = Message::default();
+    value_type.message(1, &tensor_type);
+
+    let mut value = Message::default();
+    value.string(1, name).message(2, &value_type);
+    value
+}
+
+fn node(op_type: &str, name: &str, inputs: &[&str], output: &str) -> Message {
+    let mut node = Message::default();
+    for input in inputs {
+        node.string(1, input);
+    }
+    node.string(2, output).string(3, name).string(4, op_type);
+    node
+}
+
+fn int_attribute(name: &str, value: i64) -> Message {
+    let mut attribute = Message::default();
+    attribute.string(1, name).int(3, value).int(20, ATTRIBUTE_INT);
+    attribute
+}
+
+fn float_attribute(name: &str, value: f32) -> Message {
+    let mut attribute = Message::default();
+    attribute.string(1, name).float(2, value).int(20, ATTRIBUTE_FLOAT);
+    attribute
+}
+
+// The ModelProto of `net`, as written to .onnx files
+pub fn to_onnx(net: &Net) -> Result<Vec<u8>, OnnxError> {
+    if let Some(kind) = net.kinds().into_iter().find(|&k| k != LayerKind::Dense) {
+        return Err(OnnxError::Unsupported(format!(
+            "only dense layers can be exported, not {:?}",
+            kind
+        )));
+    }
+
+    let mut graph = Message::default();
+    graph.string(2, "flappy-brain");
+
+    let sizes = net.layer_sizes();
+    let activations = net.activations();
+    let mut previous = INPUT_NAME.to_string();
+    for (index, activation) in activations.iter().enumerate() {
+        let (n_inputs, n_outputs) = (sizes[index], sizes[index + 1]);
+        let layer = format!("layer{}", index);
+
+        // Rows of bias then weights, split into the two Gemm inputs
+        let rows = net.layer_weights(index).chunks_exact(n_inputs + 1);
+        let biases: Vec<f32> = rows.clone().map(|row| row[0]).collect();
+        let weights: Vec<f32> = rows.flat_map(|row| row[1..].iter().copied()).collect();
+        let (weights_name, biases_name) = (format!("{}.weight", layer), format!("{}.bias", layer));
+        graph.message(5, &tensor(&weights_name, &[n_outputs, n_inputs], &weights));
+        graph.message(5, &tensor(&biases_name, &[n_outputs], &biases));
+
+        let sum = format!("{}.sum", layer);
+        let mut gemm = node("Gemm", &sum, &[&previous, &weights_name, &biases_name], &sum);
+        gemm.message(5, &int_attribute("transB", 1));
+        graph.message(1, &gemm);
+
+        let output = if index == activations.len() - 1 {
+            OUTPUT_NAME.to_string()
+        } else {
+            format!("{}.out", layer)
+        };
+        // Every activation maps to exact ONNX ops, one that can't must return
+        // OnnxError::Unsupported rather than export an approximation
+        match activation {
+            Activation::Sigmoid => graph.message(1, &node("Sigmoid", &output, &[&sum], &output)),
+            Activation::Tanh => graph.message(1, &node("Tanh", &output, &[&sum], &output)),
+            Activation::Relu => graph.message(1, &node("Relu", &output, &[&sum], &output)),
+            Activation::LeakyRelu => {
+                let mut leaky = node("LeakyRelu", &output, &[&sum], &output);
+                leaky.message(5, &float_attribute("alpha", 0.01));
+                graph.message(1, &leaky)
+            }
+            Activation::Linear => graph.message(1, &node("Identity", &output, &[&sum], &output)),
+            // 1 above 0, 0 otherwise: compare, then turn the bools into floats
+            Activation::Step => {
+                let (zero, above) = (format!("{}.zero", layer), format!("{}.above", layer));
+                graph.message(5, &tensor(&zero, &[], &[0.0]));
+                graph.message(1, &node("Greater", &above, &[&sum, &zero], &above));
+                let mut cast = node("Cast", &output, &[&above], &output);
+                cast.message(5, &int_attribute("to", FLOAT));
+                graph.message(1, &cast)
+            }
+        };
+        previous = output;
+    }
+
+    graph.message(11, &batch_value(INPUT_NAME, net.n_inputs()));
+    graph.message(12, &batch_value(OUTPUT_NAME, net.n_outputs()));
+
+    let mut opset = Message::default();
+    opset.int(2, ONNX_OPSET_VERSION);
+
+    let mut model = Message::default();
+    model
+        .int(1, ONNX_IR_VERSION)
+        .string(2, "flappy-rust")
+        .message(7, &graph)
+        .message(8, &opset);
+    Ok(model.0)
+}
+
+pub fn save_onnx(net: &Net, path: &Path) -> Result<(), OnnxError> {
+    fs::write(path, to_onnx(net)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use tract_onnx::prelude::*;
+
+    use super::*;
+    use crate::nn::Initialization;
+
+    const ACTIVATIONS: [Activation; 6] = [
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu,
+        Activation::Linear,
+        Activation::Step,
+    ];
+
+    fn net(activations: Vec<Activation>, kinds: Vec<LayerKind>, rng: &mut ChaCha8Rng) -> Net {
+        let sizes = vec![5, 7, 3];
+        Net::new(sizes, activations, kinds, &Initialization::default(), rng).unwrap()
+    }
+
+    // Runs the exported model on every row of `inputs` with tract
+    fn run_onnx(model: &[u8], inputs: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        let (rows, width) = (inputs.len(), inputs[0].len());
+        let model = tract_onnx::onnx()
+            .model_for_read(&mut &model[..])
+            .unwrap()
+            .with_input_fact(0, f32::fact([rows, width]).into())
+            .unwrap()
+            .into_optimized()
+            .unwrap()
+            .into_runnable()
+            .unwrap();
+
+        let flat: Vec<f32> = inputs.concat();
+        let input = tract_ndarray::Array2::from_shape_vec((rows, width), flat).unwrap();
+        let outputs = model.run(tvec!(Tensor::from(input).into())).unwrap();
+        let outputs = outputs[0].to_array_view::<f32>().unwrap();
+        outputs.outer_iter().map(|row| row.iter().copied().collect()).collect()
+    }
+
+    #[test]
+    fn exported_nets_predict_like_the_originals() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let inputs: Vec<Vec<f32>> = (0..32)
+            .map(|_| (0..5).map(|_| rng.gen_range(-2.0..2.0)).collect())
+            .collect();
+
+        // Every activation on the hidden and on the output layer
+        for activation in ACTIVATIONS {
+            for activations in [vec![activation, Activation::Linear], vec![Activation::Tanh, activation]] {
+                let net = net(activations.clone(), vec![LayerKind::Dense; 2], &mut rng);
+                let outputs = run_onnx(&to_onnx(&net).unwrap(), &inputs);
+
+                for (row, output) in inputs.iter().zip(outputs) {
+                    let expected = net.predict(row).unwrap();
+                    assert_eq!(output.len(), expected.len());
+                    for (a, b) in output.iter().zip(expected.iter()) {
+                        assert!((a - b).abs() < 1e-4, "{:?}: ONNX {} vs net {}", activations, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recurrent_nets_are_rejected() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        for kind in [LayerKind::Recurrent, LayerKind::Gated] {
+            let net = net(vec![Activation::Tanh; 2], vec![kind, LayerKind::Dense], &mut rng);
+            assert!(matches!(to_onnx(&net), Err(OnnxError::Unsupported(_))));
+        }
+    }
+}
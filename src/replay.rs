@@ -0,0 +1,188 @@
+// Recorded Human runs. The course only depends on its seed and world params and
+// the bird only on when it flapped, so those are enough to fly the exact same
+// run again.
+// Stored as b"FLPR", the version as a little endian u16, then bincode.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::WorldParams;
+
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+
+const REPLAY_MAGIC: &[u8; 4] = b"FLPR";
+const REPLAY_HEADER_LEN: usize = REPLAY_MAGIC.len() + 2;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Binary(bincode::Error),
+    NotAReplay,
+    UnsupportedVersion(u16),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "{}", err),
+            ReplayError::Binary(err) => write!(f, "Invalid replay data: {}", err),
+            ReplayError::NotAReplay => write!(f, "Not a replay file"),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "Replay version {} is not supported (expected {})",
+                version, REPLAY_FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(err: bincode::Error) -> Self {
+        ReplayError::Binary(err)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Replay {
+    // Seed the course was laid out from, see `FlappyWorld::reset`
+    pub seed: u64,
+    // Rules the run was played with, see `Config`
+    pub params: WorldParams,
+    // Ticks (counted from the reset) on which the bird flapped, in order
+    pub flaps: Vec<u64>,
+    // How the run ended, to check a playback against
+    pub ticks: u64,
+    pub score: u32,
+}
+
+impl Replay {
+    pub fn new(seed: u64, params: WorldParams) -> Self {
+        Self {
+            seed,
+            params,
+            ..Default::default()
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.extend_from_slice(&REPLAY_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)?;
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < REPLAY_HEADER_LEN || &bytes[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        Ok(bincode::deserialize(&bytes[REPLAY_HEADER_LEN..])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::world::FlappyWorld;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flappy-replay-{}-{}", std::process::id(), name))
+    }
+
+    // Flies the recorded flaps on a fresh course from the replay's seed
+    fn play(replay: &Replay) -> FlappyWorld {
+        let mut world = FlappyWorld::new(1, replay.seed, replay.params);
+        let mut flaps = replay.flaps.iter().peekable();
+        loop {
+            let flap = flaps.next_if_eq(&&world.ticks()).is_some();
+            let (_, _, done) = world.step(&[flap]).unwrap();
+            if done {
+                return world;
+            }
+        }
+    }
+
+    #[test]
+    fn a_recorded_run_plays_back_the_same() {
+        let params = WorldParams::default();
+        let mut world = FlappyWorld::new(1, 42, params);
+        let mut replay = Replay::new(world.seed(), params);
+
+        // Hover just under the next gap, then stop flapping to end the run
+        loop {
+            let observation = world.observe(0);
+            let flap = world.ticks() < 3000 && observation[4] < 0.45 && observation[3] < 0.6;
+            if flap {
+                replay.flaps.push(world.ticks());
+            }
+            let (_, _, done) = world.step(&[flap]).unwrap();
+            if done {
+                break;
+            }
+        }
+        replay.ticks = world.ticks();
+        replay.score = world.score();
+        assert!(replay.score > 0);
+
+        let path = temp_path("run.flpr");
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.seed, replay.seed);
+        assert_eq!(loaded.flaps, replay.flaps);
+
+        let played = play(&loaded);
+        assert_eq!(played.score(), loaded.score);
+        assert_eq!(played.ticks(), loaded.ticks);
+    }
+
+    #[test]
+    fn load_rejects_other_files_and_versions() {
+        let path = temp_path("bad.flpr");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.extend_from_slice(&(REPLAY_FORMAT_VERSION + 1).to_le_bytes());
+        bincode::serialize_into(&mut bytes, &Replay::default()).unwrap();
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Replay::load(&path),
+            Err(ReplayError::UnsupportedVersion(version)) if version == REPLAY_FORMAT_VERSION + 1
+        ));
+
+        bytes[..4].copy_from_slice(b"FLPB");
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(Replay::load(&path), Err(ReplayError::NotAReplay)));
+
+        fs::write(&path, b"FLP").unwrap();
+        assert!(matches!(Replay::load(&path), Err(ReplayError::NotAReplay)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
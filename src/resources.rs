@@ -1,22 +1,31 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use crate::checkpoint::Checkpoint;
+use crate::constants::NUM_BIRDS;
+use crate::leaderboard::{Leaderboard, LeaderboardEntry, ScoreMode};
+use crate::brain::Brain;
+use crate::neat::Neat;
+use crate::nn::Scratch;
+use crate::replay::Replay;
+use crate::world::FlappyWorld;
+
 #[derive(Resource, Default)]
 pub struct Game {
     pub score: u32,
     pub high_score: u32,
     pub state: GameState,
 }
- #[derive(PartialEq)]
+ #[derive(PartialEq, Default)]
 pub enum GameState {
     Active,
+    #[default]
     Inactive,
     GameOver,
 }
- 
-impl Default for GameState {
-    fn default() -> Self {
-        GameState::Inactive
-    }
-}
 
 pub fn is_game_active(game: Res<Game>) -> bool {
     game.state == GameState::Active
@@ -29,28 +38,72 @@ pub fn is_game_over(game: Res<Game>) -> bool {
     game.state == GameState::GameOver
 }
 
+// The game being played, the systems mirror it onto sprites
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameWorld(pub FlappyWorld);
+
+// A human flap waiting to be applied on the next fixed tick
+#[derive(Resource, Default)]
+pub struct FlapInput {
+    pub queued: bool,
+}
+
 #[derive(Resource)]
 pub struct SimulationState {
     pub generation: u32,
     pub birds_alive: usize,
     pub mode: GameMode,
+    // Best brain seen so far and the fitness it reached
+    pub best_brain: Option<Brain>,
+    pub best_fitness: f32,
+    // Course of the generation being flown, counting from 0
+    pub course: usize,
+    // Fitness bird `i` reached on every course of the generation so far
+    pub course_fitness: Vec<Vec<f32>>,
+    // Best score any bird reached on the courses of the generation so far
+    pub generation_score: u32,
+    // Generation whose champion (now `best_brain`) solved the game, training
+    // stops there. Only the headless trainer checks, see `check_solved`.
+    pub solved: Option<u32>,
 }
 
 impl Default for SimulationState {
     fn default() -> Self {
         Self {
             generation: 1,
-            birds_alive: 1000,
+            birds_alive: NUM_BIRDS,
             mode: GameMode::AI,
+            best_brain: None,
+            best_fitness: 0.0,
+            course: 0,
+            course_fitness: Vec::new(),
+            generation_score: 0,
+            solved: None,
         }
     }
 }
 
+impl SimulationState {
+    // Drops the courses flown so far, the generation starts over
+    pub fn reset_courses(&mut self) {
+        self.course = 0;
+        self.course_fitness.clear();
+        self.generation_score = 0;
+    }
+}
+
 #[derive(Default, PartialEq, Clone, Copy, Debug)]
 pub enum GameMode {
     Human,
     #[default]
     AI,
+    // A single bird flown by the best (or loaded) brain, played like Human
+    Champion,
+}
+
+// Training stops for good once the game is solved
+pub fn is_unsolved(sim_state: Res<SimulationState>) -> bool {
+    sim_state.solved.is_none()
 }
 
 #[allow(dead_code)]
@@ -61,4 +114,193 @@ pub fn is_ai_mode(sim_state: Res<SimulationState>) -> bool {
 #[allow(dead_code)]
 pub fn is_human_mode(sim_state: Res<SimulationState>) -> bool {
     sim_state.mode == GameMode::Human
-}
\ No newline at end of file
+}
+
+// NEAT's species and innovations, carried from one generation to the next
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct NeatState(pub Neat);
+
+// What the brain panel shows, the values are filled in by bird_brain_system
+#[derive(Resource, Default)]
+pub struct BrainView {
+    pub show: bool,
+    // Bird to follow, the fittest living one otherwise
+    pub pinned: Option<usize>,
+    // Bird shown on the last tick and the values of its net's layers then,
+    // inputs first
+    pub bird: Option<usize>,
+    pub values: Vec<Vec<f32>>,
+}
+
+// Buffers bird_brain_system keeps between ticks so thinking for the whole
+// population doesn't allocate
+#[derive(Resource, Default)]
+pub struct BrainBatch {
+    // Observations of the thinking birds, one row each
+    pub inputs: Vec<f32>,
+    pub outputs: Vec<f32>,
+    pub scratch: Scratch,
+}
+
+// When and where training checkpoints are written
+#[derive(Resource)]
+pub struct CheckpointSettings {
+    pub dir: PathBuf,
+    // Write one at the start of every Nth generation
+    pub every: Option<u32>,
+    // Write one at the end of the current tick
+    pub requested: bool,
+    pub last_saved_generation: u32,
+    // Outcome of the last attempt, for the UI
+    pub status: String,
+}
+
+impl Default for CheckpointSettings {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("checkpoints"),
+            every: None,
+            requested: false,
+            last_saved_generation: 0,
+            status: String::new(),
+        }
+    }
+}
+
+// Checkpoint the app was started with, consumed by setup
+#[derive(Resource)]
+pub struct ResumeFrom(pub Checkpoint);
+
+// Records the Human run in progress, it's written to `dir` once the bird dies
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    pub dir: PathBuf,
+    pub replay: Replay,
+    // Where the last run was saved, for the UI
+    pub status: String,
+}
+
+impl Default for ReplayRecorder {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("replays"),
+            replay: Replay::default(),
+            status: String::new(),
+        }
+    }
+}
+
+// Present while a recorded run is being watched, its flaps stand in for the
+// keyboard
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    // Index of the next flap in `replay.flaps`
+    pub next_flap: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next_flap: 0 }
+    }
+}
+
+// The leaderboard and the file it's kept in
+#[derive(Resource)]
+pub struct HighScores {
+    pub path: PathBuf,
+    pub board: Leaderboard,
+    // Name Human scores are entered under
+    pub player_name: String,
+    // Last thing that happened to the file, for the UI
+    pub status: String,
+}
+
+impl HighScores {
+    // Starts from an empty board if the file is missing or broken
+    pub fn load(path: PathBuf) -> Self {
+        let (board, warning) = Leaderboard::load_or_default(&path);
+        if let Some(warning) = &warning {
+            eprintln!("{}", warning);
+        }
+
+        let player_name = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "Player".to_string());
+
+        Self {
+            path,
+            board,
+            player_name,
+            status: warning.unwrap_or_default(),
+        }
+    }
+
+    // Puts a finished run on the board and saves it, false if it didn't make it
+    pub fn submit(&mut self, name: &str, score: u32, mode: ScoreMode) -> bool {
+        let Some(rank) = self.board.insert(LeaderboardEntry::now(name, score, mode)) else {
+            return false;
+        };
+
+        self.status = match self.board.save(&self.path) {
+            Ok(()) => format!("{} is #{} with {}", name, rank + 1, score),
+            Err(err) => format!("Failed to save high scores: {}", err),
+        };
+        println!("{}", self.status);
+        true
+    }
+}
+
+// Sounds are requested through events so the simulation systems don't need
+// an AssetServer (the headless trainer runs without one)
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub enum SoundEvent {
+    Flap,
+    Point,
+    Hit,
+}
+
+// Sent by check_alive_and_next_gen once every course of a generation is flown
+#[derive(Event, Clone, Debug)]
+pub struct GenerationEvent {
+    pub generation: u32,
+    // Fittest brain of the generation and the fitness it was selected by
+    pub champion: Brain,
+    pub fitness: f32,
+}
+
+// Stream ids for the RNGs derived from the run seed
+const COURSE_STREAM: u64 = 1;
+const EVOLUTION_STREAM: u64 = 2;
+
+// All randomness in the game comes from here. The pipe course and evolution
+// (brain init, mutation, selection) draw from separate streams of the same
+// seed, so one can't shift the other and a seed reproduces a whole run.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct SimRng {
+    pub seed: u64,
+    pub course: ChaCha8Rng,
+    pub evolution: ChaCha8Rng,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            course: Self::stream(seed, COURSE_STREAM),
+            evolution: Self::stream(seed, EVOLUTION_STREAM),
+        }
+    }
+
+    fn stream(seed: u64, stream: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(stream);
+        rng
+    }
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::new(rand::random())
+    }
+}
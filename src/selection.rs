@@ -0,0 +1,197 @@
+// Picking the parents of the next generation. Every strategy works on a
+// population sorted best first, so index 0 is the fittest bird.
+
+use std::fmt;
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionKind {
+    // Roulette wheel over the squared fitness. Falls back to rank when every
+    // bird scored 0.
+    #[default]
+    FitnessProportional,
+    // The best of `tournament_size` birds drawn at random
+    Tournament,
+    // Roulette wheel over the rank, the best of n birds weighs n and the worst
+    // 1, however far apart their fitness is
+    Rank,
+    // Any of the best `truncation` share of birds, all equally likely
+    Truncation,
+}
+
+// See `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelectionParams {
+    pub kind: SelectionKind,
+    // Tournament only
+    pub tournament_size: usize,
+    // Truncation only, share of the population that gets to breed
+    pub truncation: f32,
+}
+
+impl Default for SelectionParams {
+    fn default() -> Self {
+        Self {
+            kind: SelectionKind::FitnessProportional,
+            tournament_size: 3,
+            truncation: 0.5,
+        }
+    }
+}
+
+impl SelectionParams {
+    // A selector for a population with these fitnesses, best first
+    pub fn selector(&self, fitness: &[f32]) -> Selector {
+        let count = fitness.len();
+        let rank = || {
+            let weights = (1..=count).rev().map(|w| w as f32);
+            Selector::Weighted(WeightedIndex::new(weights).ok())
+        };
+
+        match self.kind {
+            SelectionKind::FitnessProportional => {
+                let weights = fitness.iter().map(|f| f.max(0.0).powi(2));
+                match WeightedIndex::new(weights) {
+                    Ok(dist) => Selector::Weighted(Some(dist)),
+                    Err(_) => rank(),
+                }
+            }
+            SelectionKind::Tournament => Selector::Tournament {
+                size: self.tournament_size,
+                count,
+            },
+            SelectionKind::Rank => rank(),
+            SelectionKind::Truncation => {
+                let top = (count as f32 * self.truncation).ceil() as usize;
+                Selector::Truncation(top.clamp(1, count.max(1)))
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tournament_size == 0 {
+            return Err("tournament_size must be at least 1".to_string());
+        }
+        if !(self.truncation > 0.0 && self.truncation <= 1.0) {
+            return Err(format!(
+                "truncation must be more than 0 and at most 1, got {}",
+                self.truncation
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// For the training logs
+impl fmt::Display for SelectionParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            SelectionKind::FitnessProportional => write!(f, "fitness proportional"),
+            SelectionKind::Tournament => write!(f, "tournament of {}", self.tournament_size),
+            SelectionKind::Rank => write!(f, "rank"),
+            SelectionKind::Truncation => write!(f, "truncation to the best {}", self.truncation),
+        }
+    }
+}
+
+// `SelectionParams` set up for one generation
+pub enum Selector {
+    // None only for an empty population
+    Weighted(Option<WeightedIndex<f32>>),
+    Tournament { size: usize, count: usize },
+    Truncation(usize),
+}
+
+impl Selector {
+    // Index of a parent. Don't call it on an empty population.
+    pub fn pick(&self, rng: &mut impl Rng) -> usize {
+        match self {
+            Selector::Weighted(dist) => dist.as_ref().map_or(0, |dist| dist.sample(rng)),
+            // The population is sorted, so the lowest index drawn is the fittest
+            Selector::Tournament { size, count } => {
+                (0..*size).map(|_| rng.gen_range(0..*count)).min().unwrap_or(0)
+            }
+            Selector::Truncation(top) => rng.gen_range(0..*top),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const PICKS: usize = 10_000;
+
+    fn params(kind: SelectionKind) -> SelectionParams {
+        SelectionParams {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    // How often each bird was picked
+    fn picks(selector: &Selector, count: usize, seed: u64) -> Vec<usize> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut picked = vec![0; count];
+        for _ in 0..PICKS {
+            picked[selector.pick(&mut rng)] += 1;
+        }
+        picked
+    }
+
+    // Fitter birds are picked more often, best first
+    fn assert_prefers_the_fitter(picked: &[usize]) {
+        let half = picked.len() / 2;
+        let better: usize = picked[..half].iter().sum();
+        let worse: usize = picked[half..].iter().sum();
+        assert!(better > worse, "{:?}", picked);
+        assert!(picked[0] > picked[picked.len() - 1], "{:?}", picked);
+    }
+
+    #[test]
+    fn truncation_never_picks_below_the_cutoff() {
+        let fitness: Vec<f32> = (0..10).rev().map(|f| f as f32).collect();
+        let selection = SelectionParams {
+            truncation: 0.3,
+            ..params(SelectionKind::Truncation)
+        };
+
+        let picked = picks(&selection.selector(&fitness), fitness.len(), 1);
+        assert!(picked[..3].iter().all(|&n| n > 0), "{:?}", picked);
+        assert!(picked[3..].iter().all(|&n| n == 0), "{:?}", picked);
+    }
+
+    #[test]
+    fn tournament_and_rank_prefer_the_fitter() {
+        // Rank ignores how far apart the fitnesses are
+        let fitness = [100.0, 1.0, 0.9, 0.8, 0.7, 0.6, 0.5, 0.4, 0.3, 0.2];
+
+        for kind in [SelectionKind::Tournament, SelectionKind::Rank] {
+            let picked = picks(&params(kind).selector(&fitness), fitness.len(), 2);
+            assert_prefers_the_fitter(&picked);
+            assert!(picked[1] > 0, "{:?}: {:?}", kind, picked);
+        }
+    }
+
+    #[test]
+    fn fitness_proportional_falls_back_to_rank_without_positive_fitness() {
+        let selection = params(SelectionKind::FitnessProportional);
+
+        for fitness in [[0.0; 6], [0.0, -1.0, -2.0, -3.0, -4.0, -5.0]] {
+            let selector = selection.selector(&fitness);
+            assert!(matches!(selector, Selector::Weighted(Some(_))));
+            let picked = picks(&selector, fitness.len(), 3);
+            assert!(picked.iter().all(|&n| n > 0), "{:?}", picked);
+            assert_prefers_the_fitter(&picked);
+        }
+    }
+}
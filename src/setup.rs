@@ -1,20 +1,33 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
 use crate::{
     components::*,
     // components::PressSpaceBarText,
-    constants::{WINDOW_HEIGHT, WINDOW_WIDTH},
-    utils::random_pipe_position,
+    constants::{FIXED_TIMESTEP, WINDOW_HEIGHT, WINDOW_WIDTH},
+    world::FlappyWorld,
 };
-use crate::nn::Net;
-use crate::resources::{SimulationState, GameMode};
-use crate::constants::NUM_BIRDS;
+use crate::checkpoint::Checkpoint;
+use crate::config::Config;
+use crate::brain::Brain;
+use crate::resources::*;
+use crate::systems::*;
+use rand::Rng;
 
 pub fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
     sim_state: Res<SimulationState>,
+    config: Res<Config>,
+    mut rng: ResMut<SimRng>,
+    mut neat: ResMut<NeatState>,
+    resume: Option<Res<ResumeFrom>>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
+    println!("Seed: {}", rng.seed);
+
     // Spawn a 2D camera
     commands.spawn(Camera2dBundle::default());
 
@@ -80,7 +93,7 @@ pub fn setup(
 
     // Create three score digits
     let digit_positions = [-350.0, -320.0, -290.0]; // Positions for each digit
-    for (_i, &x_pos) in digit_positions.iter().enumerate() {
+    for &x_pos in digit_positions.iter() {
         commands.spawn((
             SpriteBundle {
                 texture: asset_server.load("texture/numbers.png"),
@@ -97,7 +110,7 @@ pub fn setup(
 
     // Create three high score digits (displayed below the regular score)
     let high_score_digit_positions = [-350.0, -320.0, -290.0]; // Same x positions as regular score
-    for (_i, &x_pos) in high_score_digit_positions.iter().enumerate() {
+    for &x_pos in high_score_digit_positions.iter() {
         commands.spawn((
             SpriteBundle {
                 texture: asset_server.load("texture/numbers.png"),
@@ -131,6 +144,25 @@ pub fn setup(
         GenUi,
     ));
 
+    // Leaderboard, filled in by render_leaderboard
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_justify(JustifyText::Left),
+            transform: Transform::from_xyz(0., -120., 3.),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        LeaderboardText,
+    ));
+
 
     let bird_layout = texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
         UVec2::new(34, 24),
@@ -140,9 +172,25 @@ pub fn setup(
         None,
     ));
 
-    let num_birds = if sim_state.mode == GameMode::AI { NUM_BIRDS } else { 1 };
+    let (world, brains) = if sim_state.mode == GameMode::AI {
+        initial_population(&config, resume.as_deref().map(|r| &r.0), None, &mut rng, &mut neat)
+    } else if sim_state.mode == GameMode::Champion {
+        let champion = sim_state.best_brain.clone().map(|b| (b, Vec::new())).into_iter().collect();
+        let world = FlappyWorld::new(1, rng.course.gen(), config.world).with_fitness(config.training.fitness);
+        (world, champion)
+    } else {
+        // A replay has to be watched on the course it was recorded on
+        let (seed, params) = playback.map_or_else(
+            || (rng.course.gen(), config.world),
+            |p| (p.replay.seed, p.replay.params),
+        );
+        (FlappyWorld::new(1, seed, params), Vec::new())
+    };
+    commands.remove_resource::<ResumeFrom>();
+    let mut brains = brains.into_iter();
     
-    for _ in 0..num_birds {
+    for index in 0..world.birds().len() {
+         let (brain, memory) = brains.next().unzip();
          commands.spawn((
             SpriteBundle {
                 texture: asset_server.load("texture/bird.png"),
@@ -155,10 +203,10 @@ pub fn setup(
             },
              Bird {
                 timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-                velocity: 0.,
-                brain: if sim_state.mode == GameMode::AI { Some(Net::new(vec![5, 8, 1])) } else { None },
-                is_dead: false,
-                fitness: 0.0,
+                index,
+                brain,
+                memory: memory.unwrap_or_default(),
+                flap: false,
             },
         ));
     }
@@ -212,10 +260,8 @@ pub fn setup(
 //         UpperPipe,
 //     ));
 //   }
-  for i in 0..5 {
-    let delta_x = i as f32 * 200.;
-    let (lower_y, upper_y) = random_pipe_position();
-    let mut transform = Transform::from_xyz(350. + delta_x, lower_y, 0.5);
+ for (pair, pipe) in world.pipes().iter().enumerate() {
+    let mut transform = Transform::from_xyz(pipe.x, pipe.lower_y, 0.5);
  
     // Spawn Lower Pipe
     commands.spawn((
@@ -224,11 +270,11 @@ pub fn setup(
             transform,
             ..default()
         },
-        LowerPipe,
+        LowerPipe{pair},
     ));
  
     transform.rotate(Quat::from_rotation_z(std::f32::consts::PI));
-    transform.translation.y = upper_y;
+    transform.translation.y = pipe.upper_y;
  
     // Spawn Upper Pipe
     commands.spawn((
@@ -237,8 +283,122 @@ pub fn setup(
             transform,
             ..default()
         },
-        UpperPipe{passed:false},
+        UpperPipe{pair},
     ));
 }
-   
+
+    commands.insert_resource(GameWorld(world));
+}
+
+// The world and one brain and memory per bird for an AI run: taken over from
+// the checkpoint being resumed (RNG and NEAT state included), or fresh ones.
+// Fresh brains are `from` and mutated copies of it when given, random
+// otherwise.
+pub fn initial_population(
+    config: &Config,
+    resume: Option<&Checkpoint>,
+    from: Option<&Brain>,
+    rng: &mut SimRng,
+    neat: &mut NeatState,
+) -> (FlappyWorld, Vec<(Brain, Vec<f32>)>) {
+    if let Some(checkpoint) = resume {
+        *rng = checkpoint.rng.clone();
+        neat.0 = checkpoint.neat.clone();
+        let brains = checkpoint.population.iter().cloned().zip(checkpoint.memories.iter().cloned());
+        return (checkpoint.world.clone(), brains.collect());
+    }
+
+    let training = &config.training;
+    let brains = training
+        .new_population(training.population, from, &mut neat.innovations, &mut rng.evolution)
+        .into_iter()
+        .map(|brain| (brain, Vec::new()))
+        .collect();
+    let world = FlappyWorld::new(training.population, rng.course.gen(), config.world).with_fitness(training.fitness);
+    (world, brains)
+}
+
+// Creates the world and brain-carrying birds without any sprites for the
+// headless trainer
+pub fn spawn_headless_world(
+    commands: &mut Commands,
+    config: &Config,
+    rng: &mut SimRng,
+    neat: &mut NeatState,
+    resume: Option<&Checkpoint>,
+    from: Option<&Brain>,
+) {
+    let (world, brains) = initial_population(config, resume, from, rng, neat);
+
+    for (index, (brain, memory)) in brains.into_iter().enumerate() {
+        commands.spawn(Bird {
+            timer: Timer::from_seconds(0.2, TimerMode::Repeating),
+            index,
+            brain: Some(brain),
+            memory,
+            flap: false,
+        });
+    }
+
+    commands.insert_resource(GameWorld(world));
+}
+
+// The AI systems under MinimalPlugins (no window, sprites or audio), every
+// update advancing the simulation by exactly one fixed tick as fast as the CPU
+// allows. Picks up `resume` when given, `config`, `sim_state` and `rng` have
+// to come from it then. Otherwise the population starts from `from` or from
+// random brains.
+pub fn headless_app(
+    config: Config,
+    sim_state: SimulationState,
+    rng: SimRng,
+    checkpoints: CheckpointSettings,
+    resume: Option<Checkpoint>,
+    from: Option<Brain>,
+) -> App {
+    let tick = Duration::from_secs_f64(FIXED_TIMESTEP);
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(TimeUpdateStrategy::ManualDuration(tick))
+        .insert_resource(Time::<Fixed>::from_duration(tick))
+        .insert_resource(Game {
+            state: GameState::Active,
+            ..default()
+        })
+        .insert_resource(sim_state)
+        .insert_resource(rng)
+        .insert_resource(checkpoints)
+        .init_resource::<FlapInput>()
+        .init_resource::<BrainBatch>()
+        .init_resource::<NeatState>()
+        .add_event::<SoundEvent>()
+        .add_event::<GenerationEvent>()
+        .insert_resource(config)
+        .add_systems(
+            Startup,
+            move |mut commands: Commands,
+                  mut rng: ResMut<SimRng>,
+                  mut neat: ResMut<NeatState>,
+                  config: Res<Config>| {
+                spawn_headless_world(
+                    &mut commands,
+                    &config,
+                    &mut rng,
+                    &mut neat,
+                    resume.as_ref(),
+                    from.as_ref(),
+                )
+            },
+        )
+        .add_systems(
+            FixedUpdate,
+            (bird_brain_system, step_world, check_alive_and_next_gen, check_solved, save_checkpoints)
+                .chain()
+                .run_if(is_game_active)
+                .run_if(is_unsolved),
+        );
+
+    app.finish();
+    app.cleanup();
+    app
 }
@@ -1,13 +1,18 @@
+use crate::checkpoint::Checkpoint;
 use crate::components::*;
-use crate::constants::*;
 use crate::resources::*;
-use crate::utils::*;
+use crate::world::{FlappyWorld, Observation, BIRD_X};
 use bevy::prelude::*;
-use rand::distributions::WeightedIndex;
-use rand::prelude::Distribution;
-use rand::thread_rng;
-use crate::nn::Net;
-use crate::constants::NUM_BIRDS;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::Rng;
+use crate::leaderboard::ScoreMode;
+use crate::config::Config;
+use crate::brain::{Brain, BrainKind};
+use crate::config::TrainingConfig;
+use crate::fitness::FitnessTerms;
+use crate::neat::Innovations;
+use crate::nn::{Crossover, MutationParams, Net};
+use crate::replay::Replay;
 pub fn blink_space_bar_text(
     time: Res<Time>,
     mut query: Query<(&mut PressSpaceBarText, &mut Visibility)>,
@@ -70,44 +75,16 @@ pub fn start_game(
     mut game: ResMut<Game>,
     mut space_query: Query<(&mut PressSpaceBarText, &mut Visibility)>,
     mut game_over_query: Query<&mut Visibility, (With<GameOverText>, Without<PressSpaceBarText>)>,
-    mut bird_query: Query<(&mut Bird, &mut Transform)>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut upper_pipe_query: Query<(&mut Transform, &mut UpperPipe), (With<UpperPipe>, Without<Bird>)>,
-    mut lower_pipe_query: Query<
-        &mut Transform,
-        (With<LowerPipe>, Without<Bird>, Without<UpperPipe>),
-    >,
 ) {
-    if !keyboard_input.just_pressed(KeyCode::Space) {
+    // Restarting after a game over is handled by reset_game_after_game_over
+    if game.state != GameState::Inactive || !keyboard_input.just_pressed(KeyCode::Space) {
         return;
     }
 
     // Set game state to Active when starting
     game.state = GameState::Active;
 
-    if game.state == GameState::GameOver {
-        for (i, (mut transform, mut upper_pipe)) in upper_pipe_query.iter_mut().enumerate() {
-            let delta_x = i as f32 * 200.0 + 200.;
-
-            upper_pipe.passed = false;
-            transform.translation.x = 0.;
-            transform.translation.x += delta_x;
-        }
-
-        for (i, mut transform) in lower_pipe_query.iter_mut().enumerate() {
-            let delta_x = i as f32 * 200.0 + 200.;
-
-            transform.translation.x = 0.;
-            transform.translation.x += delta_x;
-        }
-    };
-
-    for (mut bird, mut transform) in bird_query.iter_mut() {
-        bird.velocity = 0.0;
-        transform.translation.y = 0.0;
-        transform.rotation = Quat::from_rotation_z(0.0);
-    }
-
     let (mut space, mut visibility) = space_query.single_mut();
     space.0.reset();
     *visibility = Visibility::Hidden;
@@ -116,237 +93,256 @@ pub fn start_game(
     *game_over_visibility = Visibility::Hidden;
 }
 
-pub fn gravity(
-    time: Res<Time>,
-    mut game: ResMut<Game>,
-    mut query: Query<(&mut Bird, &mut Transform)>,
-    mut game_over_query: Query<&mut Visibility, With<GameOverText>>,
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+// Key presses are only reliable in Update, so they are queued here and
+// applied by `jump` on the next simulation tick
+pub fn queue_jump(
+    mut flap_input: ResMut<FlapInput>,
     sim_state: Res<SimulationState>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
-    for (mut bird, mut transform) in query.iter_mut() {
-        if bird.is_dead { continue; }
-        let delta = time.delta().as_secs_f32();
-        let gravity = 9.8;
-        let delta_v = gravity * 150. * delta;
-        let delta_y = bird.velocity * delta;
-        let new_y = (transform.translation.y + delta_y).min(260.0);
+    if sim_state.mode != GameMode::Human || playback.is_some() { return; }
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        flap_input.queued = true;
+    }
+}
 
-        transform.translation.y = new_y;
+pub fn jump(
+    mut query: Query<&mut Bird>,
+    mut sounds: EventWriter<SoundEvent>,
+    mut flap_input: ResMut<FlapInput>,
+) {
+    if !flap_input.queued {
+        return;
+    }
+    flap_input.queued = false;
 
-        bird.velocity -= delta_v;
-        transform.translation.y += bird.velocity * delta;
+    sounds.send(SoundEvent::Flap);
 
-        // Rotate the bird
-        let rotation = bird.velocity / 600.0;
-        let max_rotation = 0.5;
-        transform.rotation = Quat::from_rotation_z(rotation.max(-max_rotation).min(max_rotation));
+    for mut bird in query.iter_mut() {
+        bird.flap = true;
+    }
+}
 
-        let ground_y = -250.0;
-        let ground_height = 112.0;
-        let bird_height = 23.0;
+// Queues the recorded flaps of the replay being watched, in place of queue_jump
+pub fn play_replay(
+    playback: Option<ResMut<ReplayPlayback>>,
+    world: Res<GameWorld>,
+    mut flap_input: ResMut<FlapInput>,
+) {
+    let Some(mut playback) = playback else { return };
 
-        let collision_point = ground_y + ground_height / 2.0 + bird_height / 2.0;
+    let next = playback.next_flap;
+    if playback.replay.flaps.get(next) == Some(&world.ticks()) {
+        flap_input.queued = true;
+        playback.next_flap += 1;
+    }
+}
 
-        if transform.translation.y < collision_point {
-            transform.translation.y = collision_point;
-            bird.velocity = 0.0;
+// Notes the flaps `jump` is about to apply. Runs between jump and step_world.
+pub fn record_replay(
+    mut recorder: ResMut<ReplayRecorder>,
+    world: Res<GameWorld>,
+    sim_state: Res<SimulationState>,
+    playback: Option<Res<ReplayPlayback>>,
+    bird_query: Query<&Bird>,
+) {
+    if sim_state.mode != GameMode::Human || playback.is_some() {
+        return;
+    }
 
-            if sim_state.mode == GameMode::Human {
-                game.state = GameState::GameOver;
-                *game_over_query.single_mut() = Visibility::Visible;
+    // First tick of a run, whatever reset the world
+    if world.ticks() == 0 {
+        recorder.replay = Replay::new(world.seed(), *world.params());
+    }
 
-                // play game over sound
-                commands.spawn(AudioBundle {
-                    source: asset_server.load("audio/hit.ogg"),
-                    settings: PlaybackSettings::DESPAWN,
-                    ..default()
-                });
-            } else {
-                 if !bird.is_dead {
-                    bird.is_dead = true;
-                    // Move bird way off screen so it's not visible
-                    transform.translation.y = -1000.0; 
-                }
-            }
-        }
+    if bird_query.iter().any(|bird| bird.flap) {
+        recorder.replay.flaps.push(world.ticks());
     }
 }
 
-// systems.rs
+// Writes the replay once the recorded run is over, or reports how a watched
+// one compares to the recording
+pub fn finish_replay(
+    mut recorder: ResMut<ReplayRecorder>,
+    world: Res<GameWorld>,
+    game: Res<Game>,
+    sim_state: Res<SimulationState>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    if sim_state.mode != GameMode::Human || game.state != GameState::GameOver {
+        return;
+    }
 
-pub fn jump(
-    mut query: Query<&mut Bird>,
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    if let Some(playback) = playback {
+        let recorded = &playback.replay;
+        let matches = world.score() == recorded.score && world.ticks() == recorded.ticks;
+        println!(
+            "Replay finished: score {} after {} ticks, recorded {} after {} ticks{}",
+            world.score(),
+            world.ticks(),
+            recorded.score,
+            recorded.ticks,
+            if matches { "" } else { " (MISMATCH)" }
+        );
+        return;
+    }
+
+    recorder.replay.ticks = world.ticks();
+    recorder.replay.score = world.score();
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = recorder
+        .dir
+        .join(format!("run-{}-score-{}.replay", timestamp, world.score()));
+
+    recorder.status = match recorder.replay.save(&path) {
+        Ok(()) => format!("Replay saved to {}", path.display()),
+        Err(err) => format!("Replay failed: {}", err),
+    };
+    println!("{}", recorder.status);
+}
+
+// Puts a Human or Champion game that just ended on the leaderboard. Runs after
+// step_world, AI generations are submitted by check_alive_and_next_gen.
+pub fn submit_score(
+    mut high_scores: ResMut<HighScores>,
+    mut game: ResMut<Game>,
+    world: Res<GameWorld>,
     sim_state: Res<SimulationState>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
-    if sim_state.mode == GameMode::AI { return; }
-    if !keyboard_input.just_pressed(KeyCode::Space) {
+    if game.state != GameState::GameOver {
         return;
     }
 
-    commands.spawn(AudioBundle {
-        source: asset_server.load("audio/wing.ogg"),
-        settings: PlaybackSettings::DESPAWN,
-        ..default()
-    });
+    let (name, mode) = match sim_state.mode {
+        // Watching a replay doesn't earn anything
+        GameMode::Human if playback.is_none() => (high_scores.player_name.clone(), ScoreMode::Human),
+        GameMode::Champion => ("Champion".to_string(), ScoreMode::Champion),
+        _ => return,
+    };
 
-    for mut bird in query.iter_mut() {
-        if bird.is_dead { continue; }
-        bird.velocity = 300.0;
+    let score = world.score();
+    if high_scores.submit(&name, score, mode) {
+        game.high_score = game.high_score.max(score);
     }
 }
 
-pub fn pipes(
-    time: Res<Time>,
-    mut upper_pipe_query: Query<(&mut UpperPipe, &mut Transform)>,
-    mut lower_pipe_query: Query<(&LowerPipe, &mut Transform), Without<UpperPipe>>,
-    mut bird_query: Query<(&mut Bird, &mut Transform), (With<Bird>, Without<LowerPipe>, Without<UpperPipe>)>,
-    mut game_over_query: Query<&mut Visibility, With<GameOverText>>,
-    asset_server: Res<AssetServer>,
+// Advances the game world by one tick with the flaps the birds asked for
+pub fn step_world(
+    mut world: ResMut<GameWorld>,
+    mut bird_query: Query<&mut Bird>,
     mut game: ResMut<Game>,
-    mut commands: Commands,
+    mut game_over_query: Query<&mut Visibility, With<GameOverText>>,
+    mut sounds: EventWriter<SoundEvent>,
     sim_state: Res<SimulationState>,
+    // Kept between ticks so stepping doesn't allocate
+    mut actions: Local<Vec<bool>>,
+    mut observations: Local<Vec<Observation>>,
+    mut rewards: Local<Vec<f32>>,
 ) {
-    let delta = time.delta().as_secs_f32();
-    let delta_x = 150. * delta;
-
-    let utmost_right_pipe = upper_pipe_query
-        .iter()
-        .max_by(|(_, a), (_, b)| a.translation.x.partial_cmp(&b.translation.x).unwrap())
-        .unwrap()
-        .1
-        .translation
-        .x;
-
-    let new_pipe_position = utmost_right_pipe + 200.0;
-    let (lower_y, upper_y) = random_pipe_position();
-    let out_of_screen_x = (-WINDOW_WIDTH / 2.) - 26.;
-
-    for (mut upper_pipe, mut transform) in upper_pipe_query.iter_mut() {
-        transform.translation.x -= delta_x;
-
-        if transform.translation.x < out_of_screen_x {
-            transform.translation.x = new_pipe_position;
-            transform.translation.y = upper_y;
-            upper_pipe.passed = false;
-        }
+    actions.clear();
+    actions.resize(world.birds().len(), false);
+    for mut bird in bird_query.iter_mut() {
+        actions[bird.index] = bird.flap;
+        bird.flap = false;
     }
 
-    for (_, mut transform) in lower_pipe_query.iter_mut() {
-        transform.translation.x -= delta_x;
+    let previous_score = world.score();
+    let done = world
+        .step_into(&actions, &mut observations, &mut rewards)
+        .expect("One action per bird");
+
+    if world.score() > previous_score {
+        game.score = world.score();
 
-        if transform.translation.x < out_of_screen_x {
-            transform.translation.x = new_pipe_position;
-            transform.translation.y = lower_y;
+        // Only for a player, the AI runs through pipes far too fast for it
+        if sim_state.mode == GameMode::Human && game.score.is_multiple_of(10) {
+            println!("Score: {}", game.score);
         }
-    }
 
-    let is_collision = |bird_transform: &Transform, pipe_transform: &Transform| -> bool {
-        let bird_x = bird_transform.translation.x;
-        let bird_y = bird_transform.translation.y;
-        let bird_width = 34.0;
-        let bird_height = 24.0;
+        // Play sound only for a single bird to avoid noise
+        if sim_state.mode != GameMode::AI {
+            sounds.send(SoundEvent::Point);
+        }
+    }
 
-        let pipe_x = pipe_transform.translation.x;
-        let pipe_y = pipe_transform.translation.y;
-        let pipe_width = 52.0;
-        let pipe_height = 320.0;
+    if done && sim_state.mode != GameMode::AI {
+        game.state = GameState::GameOver;
+        *game_over_query.single_mut() = Visibility::Visible;
 
-        let collision_x = bird_x + bird_width / 2.0 > pipe_x - pipe_width / 2.0
-            && bird_x - bird_width / 2.0 < pipe_x + pipe_width / 2.0;
-        let collision_y = bird_y + bird_height / 2.0 > pipe_y - pipe_height / 2.0
-            && bird_y - bird_height / 2.0 < pipe_y + pipe_height / 2.0;
+        // play game over sound
+        sounds.send(SoundEvent::Hit);
+    }
+}
 
-        collision_x && collision_y
-    };
+// Mirrors the world's birds onto their sprites
+pub fn sync_birds(
+    world: Res<GameWorld>,
+    sim_state: Res<SimulationState>,
+    mut query: Query<(&Bird, &mut Transform)>,
+) {
+    for (bird, mut transform) in query.iter_mut() {
+        // Birds despawned by a mode switch can outlive their world for a frame
+        let Some(state) = world.birds().get(bird.index) else { continue };
+
+        transform.translation.x = BIRD_X;
+        if !state.alive && sim_state.mode == GameMode::AI {
+            // Move bird way off screen so it's not visible
+            transform.translation.y = -1000.0;
+            continue;
+        }
 
-    for (mut bird, mut bird_transform) in bird_query.iter_mut() {
-        if bird.is_dead { continue; }
-        
-        let mut collided = false;
+        transform.translation.y = state.y;
 
-        for (_, transform) in upper_pipe_query.iter_mut() {
-            if is_collision(&bird_transform, &transform) {
-                collided = true;
-                break;
-            }
-        }
+        // Rotate the bird
+        let rotation = state.velocity / 600.0;
+        let max_rotation = 0.5;
+        transform.rotation = Quat::from_rotation_z(rotation.max(-max_rotation).min(max_rotation));
+    }
+}
 
-        if !collided {
-            for (_, transform) in lower_pipe_query.iter_mut() {
-                if is_collision(&bird_transform, &transform) {
-                    collided = true;
-                    break;
-                }
-            }
-        }
+// Mirrors the world's pipe pairs onto the pipe sprites
+pub fn sync_pipes(
+    world: Res<GameWorld>,
+    mut upper_pipe_query: Query<(&UpperPipe, &mut Transform)>,
+    mut lower_pipe_query: Query<(&LowerPipe, &mut Transform), Without<UpperPipe>>,
+) {
+    let pipes = world.pipes();
 
-        if collided {
-            if sim_state.mode == GameMode::Human {
-                game.state = GameState::GameOver;
-                *game_over_query.single_mut() = Visibility::Visible;
+    for (upper_pipe, mut transform) in upper_pipe_query.iter_mut() {
+        transform.translation.x = pipes[upper_pipe.pair].x;
+        transform.translation.y = pipes[upper_pipe.pair].upper_y;
+    }
 
-                // Play game over sound
-                commands.spawn(AudioBundle {
-                    source: asset_server.load("audio/hit.ogg"),
-                    settings: PlaybackSettings::DESPAWN,
-                    ..default()
-                });
-            } else {
-                bird.is_dead = true;
-                // Move bird off screen so it doesn't form a vertical line
-                bird_transform.translation.y = -1000.0;
-            }
-        }
+    for (lower_pipe, mut transform) in lower_pipe_query.iter_mut() {
+        transform.translation.x = pipes[lower_pipe.pair].x;
+        transform.translation.y = pipes[lower_pipe.pair].lower_y;
     }
 }
 
-pub fn score(
-    mut game: ResMut<Game>,
-    mut bird_query: Query<(&mut Bird, &Transform)>,
-    mut upper_pipe_query: Query<(&mut UpperPipe, &Transform)>,
+pub fn play_sounds(
+    mut sounds: EventReader<SoundEvent>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    sim_state: Res<SimulationState>,
 ) {
-    for (mut bird, bird_transform) in bird_query.iter_mut() {
-        for (mut upper_pipe, transform) in upper_pipe_query.iter_mut() {
-            let passed = transform.translation.x < bird_transform.translation.x;
-            let passed_state = upper_pipe.passed;
-
-            if passed && !passed_state {
-                game.score += 1;
-                upper_pipe.passed = true;
-                
-                // Reward fitness for passing a pipe
-                bird.fitness += 20.0;
-
-                if game.score % 10 == 0 { // Print less frequently
-                    println!("Score: {}", game.score);
-                }
-
-                // Play sound only in Human mode to avoid noise
-                // We don't have direct access to SimulationState here... 
-                // Wait, we need to add sim_state to arguments.
-                
-                // Let's modify the function signature first in a separate step or try to assume we added it.
-                // The tool call below this will add the argument.
-                // For now, I will modify the body assuming `sim_state` exists.
-                if sim_state.mode == GameMode::Human {
-                    commands.spawn(AudioBundle {
-                        source: asset_server.load("audio/point.ogg"),
-                        settings: PlaybackSettings::DESPAWN,
-                        ..default()
-                    });
-                }
-            }
-        }
+    for sound in sounds.read() {
+        let path = match sound {
+            SoundEvent::Flap => "audio/wing.ogg",
+            SoundEvent::Point => "audio/point.ogg",
+            SoundEvent::Hit => "audio/hit.ogg",
+        };
+
+        commands.spawn(AudioBundle {
+            source: asset_server.load(path),
+            settings: PlaybackSettings::DESPAWN,
+        });
     }
 }
+
 pub fn render_score(game: Res<Game>, mut query: Query<&mut TextureAtlas, With<ScoreText>>) {
     let score_string = format!("{:03}", game.score); // Ensure at least 3 digits, pad with zeros
     let score_digits: Vec<usize> = score_string
@@ -359,6 +355,36 @@ pub fn render_score(game: Res<Game>, mut query: Query<&mut TextureAtlas, With<Sc
     }
 }
 
+// Shows the leaderboard on the game over screen
+pub fn render_leaderboard(
+    game: Res<Game>,
+    high_scores: Res<HighScores>,
+    mut query: Query<(&mut Text, &mut Visibility), With<LeaderboardText>>,
+) {
+    let Ok((mut text, mut visibility)) = query.get_single_mut() else { return };
+
+    *visibility = if game.state == GameState::GameOver {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+
+    if high_scores.is_changed() {
+        let mut lines = vec!["HIGH SCORES".to_string()];
+        for (rank, entry) in high_scores.board.entries().iter().enumerate() {
+            lines.push(format!(
+                "{:>2}. {:<16} {:>4}  {}  {}",
+                rank + 1,
+                entry.name,
+                entry.score,
+                entry.date(),
+                entry.mode
+            ));
+        }
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
 pub fn render_high_score(
     game: Res<Game>,
     mut query: Query<&mut TextureAtlas, With<HighScoreText>>,
@@ -380,12 +406,10 @@ pub fn render_high_score(
 pub fn reset_game_after_game_over(
     mut game: ResMut<Game>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut upper_pipe_query: Query<(&mut Transform, &mut UpperPipe), (With<UpperPipe>, Without<Bird>)>,
-    mut lower_pipe_query: Query<
-        &mut Transform,
-        (With<LowerPipe>, Without<Bird>, Without<UpperPipe>),
-    >,
-    mut bird_query: Query<(&mut Bird, &mut Transform), Without<UpperPipe>>,
+    mut world: ResMut<GameWorld>,
+    mut bird_query: Query<&mut Bird>,
+    mut rng: ResMut<SimRng>,
+    playback: Option<ResMut<ReplayPlayback>>,
     mut space_query: Query<(&mut PressSpaceBarText, &mut Visibility)>,
     mut game_over_query: Query<&mut Visibility, (With<GameOverText>, Without<PressSpaceBarText>)>,
 ) {
@@ -407,23 +431,15 @@ pub fn reset_game_after_game_over(
     game.score = 0;
     game.state = GameState::Active;
 
-    // Reset all pipes to starting positions
-    for (i, (mut transform, mut upper_pipe)) in upper_pipe_query.iter_mut().enumerate() {
-        upper_pipe.passed = false;
-        transform.translation.x = 400.0 + (i as f32 * 300.0); // Adjust spacing as needed
-                                                              // You might also want to randomize pipe heights here
-    }
-
-    for (i, mut transform) in lower_pipe_query.iter_mut().enumerate() {
-        transform.translation.x = 400.0 + (i as f32 * 300.0);
+    // Fresh course, bird back at the start. A replay being watched starts over.
+    if let Some(mut playback) = playback {
+        playback.next_flap = 0;
+        world.reset(playback.replay.seed);
+    } else {
+        world.reset(rng.course.gen());
     }
-
-    // Reset bird to exact starting state
-    for (mut bird, mut transform) in bird_query.iter_mut() {
-        bird.velocity = 0.0;
-        transform.translation.x = 100.0; // Reset X position too
-        transform.translation.y = 0.0; // Or your BIRD_START_Y
-        transform.rotation = Quat::IDENTITY;
+    for mut bird in bird_query.iter_mut() {
+        bird.memory.clear();
     }
 
     // Hide all game over UI
@@ -440,209 +456,363 @@ pub fn reset_game_after_game_over(
 }
 
 pub fn bird_brain_system(
-    mut bird_query: Query<(&mut Bird, &Transform)>,
-    upper_pipe_query: Query<(&UpperPipe, &Transform)>,
-    lower_pipe_query: Query<(&LowerPipe, &Transform)>,
+    mut bird_query: Query<&mut Bird>,
+    world: Res<GameWorld>,
     sim_state: Res<SimulationState>,
+    mut batch: ResMut<BrainBatch>,
+    // Not there in the headless trainer
+    mut view: Option<ResMut<BrainView>>,
 ) {
-    if sim_state.mode != GameMode::AI {
+    if sim_state.mode == GameMode::Human {
         return;
     }
 
-    let bird_x = 0.0; // Birds are fixed at x=0 visually, but logically they are at 0
-
-    let mut closest_pipe_dist = f32::MAX;
-    let mut closest_upper_pipe: Option<&Transform> = None;
-    let mut closest_lower_pipe: Option<&Transform> = None;
+    let thinking = |bird: &Bird| bird.brain.is_some() && world.birds()[bird.index].alive;
+    let batch = &mut *batch;
+
+    // Traced before the batch runs, while the memory is still the one the
+    // bird thinks with this tick
+    if let Some(view) = view.as_deref_mut().filter(|view| view.show) {
+        let fitness = |bird: &Bird| world.birds()[bird.index].fitness;
+        let shown = bird_query
+            .iter()
+            .filter(|bird| thinking(bird) && view.pinned.is_none_or(|pinned| pinned == bird.index))
+            .max_by(|a, b| fitness(a).total_cmp(&fitness(b)));
+
+        view.bird = shown.map(|bird| bird.index);
+        view.values = match shown {
+            Some(bird @ Bird { brain: Some(Brain::Net(net)), .. }) => {
+                net.trace(&world.observe(bird.index), &bird.memory)
+            }
+            _ => Vec::new(),
+        };
+    }
 
-    // Find the next pipe
-    for (_upper_pipe, upper_transform) in upper_pipe_query.iter() {
-        // +26.0 accounts for bird/pipe width overlap roughly, ensuring we don't pick a pipe we are currently inside effectively "passed"
-        // Adjust logic if needed: we want the pipe that is strictly in front or currently intersecting
-        let dist = upper_transform.translation.x - bird_x + 52.0; // Pipe width is 52
-        if dist > 0.0 && dist < closest_pipe_dist {
-            closest_pipe_dist = dist;
-            closest_upper_pipe = Some(upper_transform);
-        }
+    // One row of observations per living bird, then all brains in one pass.
+    // The query visits the birds in the same order every time.
+    batch.inputs.clear();
+    for bird in bird_query.iter().filter(|bird| thinking(bird)) {
+        batch.inputs.extend_from_slice(&world.observe(bird.index));
     }
-    
-    // Match lower pipe
-    if let Some(upper) = closest_upper_pipe {
-         for (_, lower_transform) in lower_pipe_query.iter() {
-             if (lower_transform.translation.x - upper.translation.x).abs() < 1.0 {
-                 closest_lower_pipe = Some(lower_transform);
-                 break;
-             }
-         }
+
+    let brains = bird_query
+        .iter_mut()
+        .filter(|bird| thinking(bird))
+        .filter_map(|bird| {
+            let Bird { brain, memory, .. } = bird.into_inner();
+            brain.as_ref().map(|brain| (brain, memory))
+        });
+    if let Err(err) = Brain::predict_batch(brains, &batch.inputs, &mut batch.outputs, &mut batch.scratch) {
+        // Loaded brains are checked against the observations, so this is a bug
+        eprintln!("Brains failed to think: {}", err);
+        return;
     }
 
-    for (mut bird, transform) in bird_query.iter_mut() {
-        if bird.is_dead { continue; }
-        
-        bird.fitness += 1.0;
-
-        if let (Some(upper), Some(lower)) = (closest_upper_pipe, closest_lower_pipe) {
-                 //  inputs
-                 // 1. Bird Y (normalized)
-                 // 2. Dist to Gap Center Y (normalized)
-                 // 3. Dist to Pipe X (normalized)
-                 // 4. Velocity
-                
-                 let gap_center_y = (upper.translation.y + lower.translation.y) / 2.0;
-                 let bird_y = transform.translation.y;
-                 let dist_to_pipe_x = upper.translation.x; // bird is at 0
-                 
-                 let inputs = vec![
-                     map_range(bird_y as f64, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
-                     map_range(gap_center_y as f64, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
-                     // Expand range to -50.0 to account for when bird is crossing the pipe
-                     map_range(dist_to_pipe_x as f64, -50.0, 500.0, 0.0, 1.0).clamp(0.0, 1.0),
-                     // Expand range to -1000.0 to capture terminal velocity (falling fast)
-                     map_range(bird.velocity as f64, -1000.0, 500.0, 0.0, 1.0).clamp(0.0, 1.0),
-                     // Extra input: difference
-                     map_range((bird_y - gap_center_y) as f64, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
-                 ];
-                 
-                 // Precision Reward: Reward staying close to the center of the gap
-                 // Only apply when close to the pipe to encourage alignment
-                 let mut precision_bonus = 0.0;
-                 if dist_to_pipe_x < 100.0 && dist_to_pipe_x > -50.0 {
-                    let vertical_dist = (bird_y - gap_center_y).abs();
-                    // Bonus is higher when vertical_dist is small. 
-                    // Max bonus 0.5 per frame roughly
-                    if vertical_dist < 50.0 {
-                        precision_bonus = (50.0 - vertical_dist) / 50.0;
-                    }
-                 }
-                 
-                 let should_jump = if let Some(brain) = &bird.brain {
-                     brain.predict(&inputs)[0] > 0.5
-                 } else {
-                     false
-                 };
-
-                 bird.fitness += precision_bonus;
-                 
-                 if should_jump {
-                     bird.velocity = 300.0;
-                 }
+    // Brains have a single output, flap or not
+    let birds = bird_query.iter_mut().filter(|bird| thinking(bird));
+    for (mut bird, &output) in birds.zip(batch.outputs.iter()) {
+        if output > 0.5 {
+            bird.flap = true;
         }
     }
 }
 
-fn map_range(val: f64, in_min: f64, in_max: f64, out_min: f64, out_max: f64) -> f64 {
-    (val - in_min) * (out_max - out_min) / (in_max - in_min) + out_min
-}
-
 pub fn check_alive_and_next_gen(
-    mut bird_query: Query<(&mut Bird, &mut Transform)>,
-    mut upper_pipe_query: Query<(&mut Transform, &mut UpperPipe), (With<UpperPipe>, Without<Bird>)>,
-    mut lower_pipe_query: Query<&mut Transform, (With<LowerPipe>, Without<Bird>, Without<UpperPipe>)>,
+    mut bird_query: Query<&mut Bird>,
+    mut world: ResMut<GameWorld>,
     mut sim_state: ResMut<SimulationState>,
     mut game: ResMut<Game>,
+    mut rng: ResMut<SimRng>,
+    mut neat: ResMut<NeatState>,
+    config: Res<Config>,
+    // Not kept by the headless trainer
+    high_scores: Option<ResMut<HighScores>>,
+    mut generations: EventWriter<GenerationEvent>,
 ) {
     if sim_state.mode != GameMode::AI {
         return;
     }
 
-    let alive_count = bird_query.iter().filter(|(b, _)| !b.is_dead).count();
+    let alive_count = world.alive_count();
     sim_state.birds_alive = alive_count;
 
-    if alive_count == 0 {
+    // Birds still flying when the course hits its limits are scored as they are
+    let evaluation = &config.training.evaluation;
+    if evaluation.course_over(&world) {
+        let population = world.birds().len();
+        if sim_state.course_fitness.len() != population {
+            sim_state.course_fitness = vec![Vec::new(); population];
+        }
+        for (scores, bird) in sim_state.course_fitness.iter_mut().zip(world.birds()) {
+            scores.push(bird.fitness);
+        }
+        sim_state.course += 1;
+        sim_state.generation_score = sim_state.generation_score.max(world.score());
+
+        // What the course was rewarded for on average
+        let weights = world.fitness_weights();
+        let average = FitnessTerms::average(world.birds().iter().map(|b| &b.terms));
+        println!(
+            "  Course {}/{} average fitness: {}",
+            sim_state.course,
+            evaluation.courses,
+            weights.describe(&average)
+        );
+
+        // The same brains fly on until they've seen every course
+        if sim_state.course < evaluation.courses {
+            for mut bird in bird_query.iter_mut() {
+                bird.memory.clear();
+                bird.flap = false;
+            }
+            world.reset(rng.course.gen());
+            game.score = 0;
+            return;
+        }
+
+        let course_fitness = std::mem::take(&mut sim_state.course_fitness);
+        let score = sim_state.generation_score;
+        sim_state.reset_courses();
         sim_state.generation += 1;
         
         // Collect all birds
-        let mut birds: Vec<(Net, f32)> = bird_query.iter()
-            .map(|(b, _)| (b.brain.clone().unwrap(), b.fitness))
+        let mut birds: Vec<(Brain, f32)> = bird_query.iter()
+            .map(|b| (b.brain.clone().unwrap(), evaluation.aggregate(&course_fitness[b.index])))
             .collect();
             
         // Sort by fitness descending
         birds.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
         
-        println!("Evolving gen {} -> {}. Max Fitness: {}", sim_state.generation - 1, sim_state.generation, birds.first().map(|b| b.1).unwrap_or(0.0));
-
-        let mut new_brains = Vec::new();
-        
-        // Elitism: Keep top 4 best performing brains EXACTLY as they are
-        for i in 0..4 {
-            if i < birds.len() {
-                new_brains.push(birds[i].0.clone());
+        // All brains are of one kind, they come from the same config or brain
+        let kind = birds.first().map_or(config.training.brain, |(brain, _)| brain.kind());
+
+        // NEAT picks parents within each species instead
+        let selection = match kind {
+            BrainKind::Net => format!("{} selection", config.training.selection),
+            BrainKind::Neat => format!("{} species", neat.species().len()),
+        };
+        println!(
+            "Evolving gen {} -> {}. Max Fitness: {}, {}",
+            sim_state.generation - 1,
+            sim_state.generation,
+            birds.first().map(|b| b.1).unwrap_or(0.0),
+            selection
+        );
+
+        // Remember the best brain across all generations
+        if let Some((best_brain, best_fitness)) = birds.first() {
+            if sim_state.best_brain.is_none() || *best_fitness > sim_state.best_fitness {
+                sim_state.best_brain = Some(best_brain.clone());
+                sim_state.best_fitness = *best_fitness;
             }
         }
-        
-        // Generate the rest
-        let mut rng = thread_rng();
-        let remaining_slots = NUM_BIRDS - new_brains.len();
-        
-        // Selection: weighted by fitness, but favor top 50% significantly if possible?
-        // Standard weighted index is fine if weights are distinct enough.
-        // Let's square the fitness to exaggerate differences
-        let weights: Vec<f32> = birds.iter().map(|(_, f)| f.powf(2.0)).collect();
-
-        if let Ok(dist) = WeightedIndex::new(weights) {
-             for _ in 0..remaining_slots {
-                let parent_idx = dist.sample(&mut rng);
-                let mut child_brain = birds[parent_idx].0.clone();
-                child_brain.mutate();
-                new_brains.push(child_brain);
+
+        // One entry per generation, with its best course
+        if let Some(mut high_scores) = high_scores {
+            let name = format!("Generation {}", sim_state.generation - 1);
+            if high_scores.submit(&name, score, ScoreMode::AiChampion) {
+                game.high_score = game.high_score.max(score);
             }
-        } else {
-             // Fallback if all 0 fitness (shouldn't happen usually)
-             for _ in 0..remaining_slots {
-                 let mut child_brain = birds[0].0.clone(); // Just clone the first
-                 child_brain.mutate();
-                 new_brains.push(child_brain);
-             }
         }
 
-        // Assign new brains
-        // IMPORTANT: We must re-assign based on index or just refill. 
-        // Bevy query iteration order isn't guaranteed relative to our vector if we didn't track entities.
-        // But since we are replacing all of them, we can just zip.
-        
-        for ((mut bird, mut transform), new_brain) in bird_query.iter_mut().zip(new_brains.into_iter()) {
-            bird.is_dead = false;
-            bird.velocity = 0.0;
-            bird.fitness = 0.0;
-            bird.brain = Some(new_brain);
-            transform.translation.y = 0.0;
-            transform.translation.x = 0.0; 
-            transform.rotation = Quat::IDENTITY;
+        // The trainer checks whether it solved the game, see check_solved
+        if let Some((champion, fitness)) = birds.first() {
+            generations.send(GenerationEvent {
+                generation: sim_state.generation - 1,
+                champion: champion.clone(),
+                fitness: *fitness,
+            });
         }
 
-        // 4. Reset pipes
-        // Match upper and lower pipes by index to ensure they get the same random position
-        let mut upper_iter = upper_pipe_query.iter_mut();
-        let mut lower_iter = lower_pipe_query.iter_mut();
-        
-        let mut i = 0;
-        while let (Some((mut upper_transform, mut upper_pipe)), Some(mut lower_transform)) = (upper_iter.next(), lower_iter.next()) {
-             upper_pipe.passed = false;
-             let delta_x = i as f32 * 200.0 + 400.0;
-             
-             let (lower_y, upper_y) = random_pipe_position();
-             
-             upper_transform.translation.x = delta_x;
-             upper_transform.translation.y = upper_y;
-             
-             lower_transform.translation.x = delta_x;
-             lower_transform.translation.y = lower_y;
-             
-             i += 1;
+        // Annealed for the generation being bred
+        let mutation = config.training.mutation.at_generation(sim_state.generation);
+
+        let new_brains: Vec<Brain> = match kind {
+            BrainKind::Net => {
+                let nets = birds.into_iter().filter_map(|(brain, fitness)| match brain {
+                    Brain::Net(net) => Some((net, fitness)),
+                    Brain::Neat(_) => None,
+                });
+                next_net_generation(nets.collect(), &config.training, &mutation, &mut rng.evolution)
+                    .into_iter()
+                    .map(Brain::Net)
+                    .collect()
+            }
+            BrainKind::Neat => {
+                let genomes = birds.into_iter().filter_map(|(brain, fitness)| match brain {
+                    Brain::Neat(genome) => Some((genome, fitness)),
+                    Brain::Net(_) => None,
+                });
+                neat.next_generation(genomes.collect(), &config.training.neat, &mutation, &mut rng.evolution)
+                    .into_iter()
+                    .map(Brain::Neat)
+                    .collect()
+            }
+        };
+
+        // Assign new brains. Which bird gets which brain doesn't matter since
+        // every bird in the world starts over anyway.
+        for (mut bird, new_brain) in bird_query.iter_mut().zip(new_brains) {
+            bird.brain = Some(new_brain);
+            bird.memory.clear();
+            bird.flap = false;
         }
+
+        // Reset birds and pipes on a new course
+        world.reset(rng.course.gen());
         
         game.score = 0;
     }
 }
 
+// Ends training once the champion of a generation gets through the held-out
+// courses. Those are whole games flown on the spot, so only the headless
+// trainer runs it.
+pub fn check_solved(
+    mut generations: EventReader<GenerationEvent>,
+    mut sim_state: ResMut<SimulationState>,
+    config: Res<Config>,
+) {
+    for done in generations.read() {
+        if sim_state.solved.is_some() {
+            continue;
+        }
+
+        match config.training.solved.is_solved(&done.champion, config.world) {
+            Ok(true) => {
+                println!("Solved in generation {} by {}", done.generation, done.champion);
+                sim_state.solved = Some(done.generation);
+                sim_state.best_brain = Some(done.champion.clone());
+                sim_state.best_fitness = done.fitness;
+            }
+            Ok(false) => {}
+            Err(err) => eprintln!("Champion failed to think: {}", err),
+        }
+    }
+}
+
+// The next generation of a population of nets, `birds` sorted best first
+fn next_net_generation(
+    birds: Vec<(Net, f32)>,
+    training: &TrainingConfig,
+    mutation: &MutationParams,
+    rng: &mut impl Rng,
+) -> Vec<Net> {
+    // Elitism: Keep the best performing brains EXACTLY as they are
+    let mut new_brains: Vec<Net> = birds
+        .iter()
+        .take(training.elitism)
+        .map(|(net, _)| net.clone())
+        .collect();
+    
+    // Generate the rest
+    let remaining_slots = birds.len() - new_brains.len();
+    if remaining_slots == 0 {
+        return new_brains;
+    }
+
+    let fitness: Vec<f32> = birds.iter().map(|(_, f)| *f).collect();
+    let selector = training.selection.selector(&fitness);
+    for _ in 0..remaining_slots {
+        let parent = &birds[selector.pick(rng)].0;
+        // A second parent only when there's something to combine. Parents of
+        // different shapes (after loading a brain, say) can't be combined, the
+        // child is then a copy of the first.
+        let mut child_brain = match training.crossover {
+            Crossover::None => parent.clone(),
+            method => {
+                let other = &birds[selector.pick(rng)].0;
+                parent.crossover(other, method, rng).unwrap_or_else(|_| parent.clone())
+            }
+        };
+        child_brain.mutate(mutation, rng);
+        new_brains.push(child_brain);
+    }
+
+    new_brains
+}
+
+// Writes periodic checkpoints at the start of a generation and on-demand ones
+// wherever the run currently is
+pub fn save_checkpoints(
+    mut settings: ResMut<CheckpointSettings>,
+    sim_state: Res<SimulationState>,
+    world: Res<GameWorld>,
+    rng: Res<SimRng>,
+    neat: Res<NeatState>,
+    config: Res<Config>,
+    bird_query: Query<&Bird>,
+) {
+    // A world that was just reset on the first course means a new
+    // generation just started
+    let periodic = settings.every.is_some_and(|every| {
+        world.ticks() == 0
+            && sim_state.course == 0
+            && sim_state.generation > settings.last_saved_generation
+            && (sim_state.generation - 1).is_multiple_of(every)
+    });
+    if !periodic && !settings.requested {
+        return;
+    }
+    settings.requested = false;
+
+    if sim_state.mode != GameMode::AI {
+        settings.status = "Checkpoints are only taken in AI mode".to_string();
+        return;
+    }
+
+    let path = settings.dir.join(format!(
+        "gen-{:05}-tick-{:06}.ckpt",
+        sim_state.generation,
+        world.ticks()
+    ));
+    let brains = bird_query.iter().filter_map(|bird| {
+        bird.brain.as_ref().map(|brain| (bird.index, brain, bird.memory.as_slice()))
+    });
+
+    settings.status = match Checkpoint::capture(&sim_state, &world, &rng, &neat, &config, brains)
+        .and_then(|checkpoint| checkpoint.save(&path))
+    {
+        Ok(()) => {
+            settings.last_saved_generation = sim_state.generation;
+            format!("Checkpoint saved to {}", path.display())
+        }
+        Err(err) => format!("Checkpoint failed: {}", err),
+    };
+    println!("{}", settings.status);
+}
+
+// Replaces the population with `brain`: the first bird gets it as is, the
+// rest get mutated copies
+pub fn seed_population<'a>(
+    brain: &Brain,
+    birds: impl Iterator<Item = Mut<'a, Bird>>,
+    training: &TrainingConfig,
+    innovations: &mut Innovations,
+    rng: &mut impl Rng,
+) {
+    let birds: Vec<_> = birds.collect();
+    let brains = training.new_population(birds.len(), Some(brain), innovations, rng);
+    for (mut bird, brain) in birds.into_iter().zip(brains) {
+        bird.brain = Some(brain);
+        bird.memory.clear();
+        bird.flap = false;
+    }
+}
+
 pub fn update_gen_ui(
     sim_state: Res<SimulationState>,
+    world: Res<GameWorld>,
     mut query: Query<&mut Text, With<GenUi>>,
 ) {
     for mut text in query.iter_mut() {
         if sim_state.mode == GameMode::AI {
             text.sections[0].value = format!("Gen: {}\nAlive: {}", sim_state.generation, sim_state.birds_alive);
             text.sections[0].style.color = Color::WHITE;
+        } else if sim_state.mode == GameMode::Champion {
+            // The seed tells courses apart when judging how well the brain generalizes
+            text.sections[0].value = format!("Champion\nSeed: {}", world.seed());
+            text.sections[0].style.color = Color::WHITE;
         } else {
              text.sections[0].value = "".to_string(); 
         }
@@ -657,10 +827,15 @@ pub fn toggle_game_mode(
     asset_server: Res<AssetServer>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
     mut game: ResMut<Game>,
-    mut upper_pipes: Query<&mut Transform, With<UpperPipe>>,
-    mut lower_pipes: Query<&mut Transform, (With<LowerPipe>, Without<UpperPipe>)>,
+    mut world: ResMut<GameWorld>,
+    mut rng: ResMut<SimRng>,
+    mut neat: ResMut<NeatState>,
+    config: Res<Config>,
 ) {
      if keyboard_input.just_pressed(KeyCode::KeyM) {
+         // Switching modes ends any replay being watched
+         commands.remove_resource::<ReplayPlayback>();
+
          for entity in bird_query.iter() {
              commands.entity(entity).despawn();
          }
@@ -673,8 +848,19 @@ pub fn toggle_game_mode(
             None,
         ));
 
-         if sim_state.mode == GameMode::AI {
-             sim_state.mode = GameMode::Human;
+         // AI -> Human -> Champion -> AI, Champion only once there's a brain to fly
+         sim_state.mode = match sim_state.mode {
+             GameMode::AI => GameMode::Human,
+             GameMode::Human if sim_state.best_brain.is_some() => GameMode::Champion,
+             GameMode::Human | GameMode::Champion => GameMode::AI,
+         };
+
+         if sim_state.mode != GameMode::AI {
+             let brain = match sim_state.mode {
+                 GameMode::Champion => sim_state.best_brain.clone(),
+                 _ => None,
+             };
+             world.0 = FlappyWorld::new(1, rng.course.gen(), config.world).with_fitness(config.training.fitness);
               commands.spawn((
                 SpriteBundle {
                     texture: asset_server.load("texture/bird.png"),
@@ -687,34 +873,27 @@ pub fn toggle_game_mode(
                 },
                  Bird {
                     timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-                    velocity: 0.,
-                    brain: None, 
-                    is_dead: false,
-                    fitness: 0.0,
+                    index: 0,
+                    brain,
+                    memory: Vec::new(),
+                    flap: false,
                 },
             ));
-            game.score = 0;
-            // Reset pipes for human mode
-            let mut upper_iter = upper_pipes.iter_mut();
-            let mut lower_iter = lower_pipes.iter_mut();
-            let mut i = 0;
-            while let (Some(mut upper_transform), Some(mut lower_transform)) = (upper_iter.next(), lower_iter.next()) {
-                 let delta_x = i as f32 * 200.0 + 400.0;
-                 let (lower_y, upper_y) = random_pipe_position();
-                 
-                 upper_transform.translation.x = delta_x;
-                 upper_transform.translation.y = upper_y;
-                 
-                 lower_transform.translation.x = delta_x;
-                 lower_transform.translation.y = lower_y;
-                 i += 1;
-            }
-
          } else {
-             sim_state.mode = GameMode::AI;
              sim_state.generation = 1;
-             
-             for _ in 0..NUM_BIRDS {
+             sim_state.reset_courses();
+             let training = &config.training;
+             world.0 = FlappyWorld::new(training.population, rng.course.gen(), config.world)
+                 .with_fitness(training.fitness);
+             // Carry on from the best (or loaded) brain rather than starting over
+             let brains = training.new_population(
+                 training.population,
+                 sim_state.best_brain.as_ref(),
+                 &mut neat.innovations,
+                 &mut rng.evolution,
+             );
+
+             for (index, brain) in brains.into_iter().enumerate() {
                 commands.spawn((
                     SpriteBundle {
                         texture: asset_server.load("texture/bird.png"),
@@ -727,10 +906,10 @@ pub fn toggle_game_mode(
                     },
                      Bird {
                         timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-                        velocity: 0.,
-                        brain: Some(Net::new(vec![5, 8, 1])), 
-                        is_dead: false,
-                        fitness: 0.0,
+                        index,
+                        brain: Some(brain),
+                        memory: Vec::new(),
+                        flap: false,
                     },
                 ));
             }
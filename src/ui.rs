@@ -1,13 +1,24 @@
+use std::path::Path;
+
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
-use crate::resources::{SimulationState, GameMode};
+use rand::Rng;
+use crate::brain_file::{load_brain, save_brain};
+use crate::brain::Brain;
+use crate::nn::Net;
+use crate::resources::{BrainView, CheckpointSettings, SimulationState, GameMode, GameWorld, HighScores, NeatState, ReplayRecorder, SimRng};
 use crate::components::Bird;
+use crate::config::Config;
+use crate::systems::seed_population;
 
 #[derive(Resource)]
 pub struct UiState {
     pub show_one_bird: bool,
     pub fast_mode: bool,
     pub show_ui: bool,
+    pub brain_path: String,
+    // Outcome of the last save or load
+    pub brain_status: String,
 }
 
 impl Default for UiState {
@@ -16,31 +27,61 @@ impl Default for UiState {
             show_one_bird: false,
             fast_mode: false,
             show_ui: true,
+            brain_path: "best_brain.json".to_string(),
+            brain_status: String::new(),
         }
     }
 }
 
 pub fn ui_system(
     mut contexts: EguiContexts,
-    sim_state: Res<SimulationState>,
+    mut sim_state: ResMut<SimulationState>,
+    mut world: ResMut<GameWorld>,
     mut ui_state: ResMut<UiState>,
-    mut bird_query: Query<(&mut Visibility, &Bird)>,
+    mut bird_query: Query<(&mut Visibility, &mut Bird)>,
     mut time: ResMut<Time<Virtual>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut rng: ResMut<SimRng>,
+    mut neat: ResMut<NeatState>,
+    mut checkpoints: ResMut<CheckpointSettings>,
+    replays: Res<ReplayRecorder>,
+    mut high_scores: ResMut<HighScores>,
+    config: Res<Config>,
 ) {
     if keyboard_input.just_pressed(KeyCode::Escape) {
         ui_state.show_ui = !ui_state.show_ui;
     }
-    
+
     if !ui_state.show_ui { return; }
 
+    let mut save_clicked = false;
+    let mut load_clicked = false;
+
     egui::Window::new("Flappy AI")
         .default_pos(egui::pos2(10.0, 10.0))
         .show(contexts.ctx_mut(), |ui| {
             ui.heading("Stats");
             ui.label(format!("Generation: {}", sim_state.generation));
             ui.label(format!("Alive: {}", sim_state.birds_alive));
+            if config.training.evaluation.courses > 1 {
+                ui.label(format!("Course: {}/{}", sim_state.course + 1, config.training.evaluation.courses));
+            }
             ui.label(format!("Mode: {:?}", sim_state.mode));
+            if let Some(best_brain) = &sim_state.best_brain {
+                ui.label(format!("Best brain: {}", best_brain));
+            }
+            let fittest = world
+                .birds()
+                .iter()
+                .filter(|b| b.alive)
+                .max_by(|a, b| a.fitness.total_cmp(&b.fitness));
+            if let Some(bird) = fittest.filter(|_| sim_state.mode == GameMode::AI) {
+                ui.label(format!("Best living bird: {} pipes", bird.score));
+                ui.label(format!("Its fitness: {}", world.fitness_weights().describe(&bird.terms)));
+            }
+            if !neat.species().is_empty() {
+                ui.label(format!("Species: {}", neat.species().len()));
+            }
 
             ui.separator();
 
@@ -54,6 +95,8 @@ pub fn ui_system(
                 }
             }
             
+            // Speeding up virtual time runs more fixed ticks per frame, the
+            // ticks themselves stay the same size
             if ui.checkbox(&mut ui_state.fast_mode, "Fast Mode (5x Speed)").changed() {
                  if ui_state.fast_mode {
                      time.set_relative_speed(5.0);
@@ -62,18 +105,140 @@ pub fn ui_system(
                  }
             }
 
+            ui.separator();
+
+            ui.heading("Brains");
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut ui_state.brain_path);
+            });
+            ui.horizontal(|ui| {
+                save_clicked = ui.button("Save best brain").clicked();
+                load_clicked = ui.button("Load brain").clicked();
+            });
+            if !ui_state.brain_status.is_empty() {
+                ui.label(&ui_state.brain_status);
+            }
+
+            ui.separator();
+
+            ui.heading("Checkpoints");
+            let mut periodic = checkpoints.every.is_some();
+            if ui.checkbox(&mut periodic, "Checkpoint every 10 generations").changed() {
+                checkpoints.every = periodic.then_some(10);
+            }
+            if ui.button("Save checkpoint").clicked() {
+                checkpoints.requested = true;
+            }
+            if !checkpoints.status.is_empty() {
+                ui.label(&checkpoints.status);
+            }
+
+            if !replays.status.is_empty() {
+                ui.separator();
+                ui.heading("Replays");
+                ui.label(&replays.status);
+            }
+
+            ui.separator();
+
+            ui.heading("High Scores");
+            ui.horizontal(|ui| {
+                ui.label("Player:");
+                ui.text_edit_singleline(&mut high_scores.player_name);
+            });
+            egui::Grid::new("leaderboard").striped(true).show(ui, |ui| {
+                for (rank, entry) in high_scores.board.entries().iter().enumerate() {
+                    ui.label(format!("{}.", rank + 1));
+                    ui.label(&entry.name);
+                    ui.label(entry.score.to_string());
+                    ui.label(entry.date());
+                    ui.label(entry.mode.to_string());
+                    ui.end_row();
+                }
+            });
+            if !high_scores.status.is_empty() {
+                ui.label(&high_scores.status);
+            }
+
             ui.separator();
             ui.label("Controls:");
-            ui.label("M: Toggle Mode");
+            ui.label("M: Switch Mode (AI, Human, Champion)");
+            ui.label("B: Toggle Brain View");
             ui.label("Esc: Toggle UI");
         });
 
+    if save_clicked {
+        // Best of all finished generations, or the fittest bird so far while
+        // the first generation is still flying
+        let best_brain = sim_state.best_brain.clone().or_else(|| {
+            bird_query
+                .iter()
+                .filter(|(_, bird)| bird.brain.is_some())
+                .max_by(|(_, a), (_, b)| {
+                    let fitness = |bird: &Bird| world.birds().get(bird.index).map_or(0.0, |b| b.fitness);
+                    fitness(a).total_cmp(&fitness(b))
+                })
+                .and_then(|(_, bird)| bird.brain.clone())
+        });
+
+        let path = Path::new(&ui_state.brain_path);
+        ui_state.brain_status = match best_brain {
+            None => "No brain to save in Human mode".to_string(),
+            Some(brain) => match save_brain(&brain, path) {
+                Ok(()) => format!("Saved to {}", path.display()),
+                Err(err) => format!("Save failed: {}", err),
+            },
+        };
+    }
+
+    if load_clicked {
+        let path = Path::new(&ui_state.brain_path);
+        ui_state.brain_status = match load_brain(path) {
+            Err(err) => format!("Load failed: {}", err),
+            Ok(brain) if sim_state.mode == GameMode::AI => {
+                // Restart evolution from the loaded brain
+                seed_population(
+                    &brain,
+                    bird_query.iter_mut().map(|(_, bird)| bird),
+                    &config.training,
+                    &mut neat.innovations,
+                    &mut rng.evolution,
+                );
+                world.reset(rng.course.gen());
+                sim_state.generation = 1;
+                sim_state.reset_courses();
+                sim_state.best_brain = Some(brain);
+                sim_state.best_fitness = 0.0;
+                format!("Loaded {}, population restarted from it", path.display())
+            }
+            Ok(brain) if sim_state.mode == GameMode::Champion => {
+                // The champion starts over on a new course with the loaded brain
+                for (_, mut bird) in bird_query.iter_mut() {
+                    bird.brain = Some(brain.clone());
+                    bird.memory.clear();
+                    bird.flap = false;
+                }
+                world.reset(rng.course.gen());
+                sim_state.best_brain = Some(brain);
+                sim_state.best_fitness = 0.0;
+                format!("Loaded {}, the champion flies it now", path.display())
+            }
+            Ok(brain) => {
+                sim_state.best_brain = Some(brain);
+                sim_state.best_fitness = 0.0;
+                format!("Loaded {}, AI and Champion mode will start from it", path.display())
+            }
+        };
+    }
+
     // Handle "Show One Bird" logic per frame if active
     if ui_state.show_one_bird && sim_state.mode == GameMode::AI {
          // Show only the first living bird
          let mut found_one = false;
          for (mut vis, bird) in bird_query.iter_mut() {
-             if !found_one && !bird.is_dead {
+             let alive = world.birds().get(bird.index).is_some_and(|b| b.alive);
+             if !found_one && alive {
                  *vis = Visibility::Visible;
                  found_one = true;
              } else {
@@ -82,3 +247,143 @@ pub fn ui_system(
          }
     }
 }
+
+// The net of the bird in `BrainView` with the values of its last prediction
+pub fn brain_view_ui(
+    mut contexts: EguiContexts,
+    mut view: ResMut<BrainView>,
+    ui_state: Res<UiState>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    bird_query: Query<&Bird>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyB) {
+        view.show = !view.show;
+    }
+    if !view.show || !ui_state.show_ui {
+        return;
+    }
+
+    let view = &mut *view;
+    let shown = view
+        .bird
+        .and_then(|index| bird_query.iter().find(|bird| bird.index == index));
+    let last_index = bird_query.iter().map(|bird| bird.index).max().unwrap_or(0);
+
+    egui::Window::new("Brain")
+        .default_pos(egui::pos2(10.0, 420.0))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| match view.pinned.as_mut() {
+                Some(pinned) => {
+                    ui.add(egui::DragValue::new(pinned).range(0..=last_index).prefix("Pinned bird "));
+                    if ui.button("Unpin").clicked() {
+                        view.pinned = None;
+                    }
+                }
+                None => {
+                    ui.label(match view.bird {
+                        Some(bird) => format!("Fittest bird {}", bird),
+                        None => "No bird flying".to_string(),
+                    });
+                    if ui.button("Pin").clicked() {
+                        view.pinned = Some(view.bird.unwrap_or(0));
+                    }
+                }
+            });
+
+            match shown.and_then(|bird| bird.brain.as_ref()) {
+                Some(Brain::Net(net)) if !view.values.is_empty() => draw_net(ui, net, &view.values),
+                Some(Brain::Neat(_)) => {
+                    ui.label("Only nets can be drawn");
+                }
+                _ if view.pinned.is_some() => {
+                    ui.label("The pinned bird isn't flying");
+                }
+                _ => {}
+            }
+        });
+}
+
+// Nodes as circles, brighter the more active (green positive, red negative),
+// and connections as lines, green or red by sign and thicker the larger
+fn draw_net(ui: &mut egui::Ui, net: &Net, values: &[Vec<f32>]) {
+    // The values were traced on the last tick, the bird may have been given a
+    // brain of another shape since (loaded or seeded)
+    if values.iter().map(Vec::len).ne(net.layer_sizes()) {
+        return;
+    }
+
+    const NODE_RADIUS: f32 = 6.0;
+    let size = egui::vec2(320.0, 200.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect.shrink(NODE_RADIUS * 2.0);
+
+    let position = |layer: usize, node: usize| {
+        let layers = values.len().max(2) - 1;
+        let nodes = values[layer].len();
+        let x = rect.left() + rect.width() * layer as f32 / layers as f32;
+        let y = rect.top() + rect.height() * (node as f32 + 0.5) / nodes as f32;
+        egui::pos2(x, y)
+    };
+    let signed = |value: f32| {
+        let strength = value.abs().min(1.0);
+        let color = if value >= 0.0 {
+            egui::Color32::GREEN
+        } else {
+            egui::Color32::RED
+        };
+        egui::Color32::DARK_GRAY.lerp_to_gamma(color, strength)
+    };
+
+    for (layer, layer_values) in values.iter().enumerate().skip(1) {
+        for node in 0..layer_values.len() {
+            for (input, &weight) in net.node_weights(layer - 1, node).iter().enumerate() {
+                let stroke = egui::Stroke::new(0.5 + weight.abs().min(2.0), signed(weight));
+                painter.line_segment([position(layer - 1, input), position(layer, node)], stroke);
+            }
+        }
+    }
+
+    for (layer, layer_values) in values.iter().enumerate() {
+        for (node, &value) in layer_values.iter().enumerate() {
+            let stroke = egui::Stroke::new(1.0, egui::Color32::WHITE);
+            painter.circle(position(layer, node), NODE_RADIUS, signed(value), stroke);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::nn::{Activation, Initialization, LayerKind};
+
+    use super::*;
+
+    fn net(layer_sizes: Vec<usize>, rng: &mut ChaCha8Rng) -> Net {
+        let layers = layer_sizes.len() - 1;
+        let activations = vec![Activation::Sigmoid; layers];
+        let kinds = vec![LayerKind::Dense; layers];
+        Net::new(layer_sizes, activations, kinds, &Initialization::default(), rng).unwrap()
+    }
+
+    fn draw(net: &Net, values: &[Vec<f32>]) {
+        let ctx = egui::Context::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| draw_net(ui, net, values));
+        });
+    }
+
+    #[test]
+    fn draws_values_traced_from_a_brain_that_was_swapped_out() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let old = net(vec![5, 8, 1], &mut rng);
+        let values = old.trace(&[0.5; 5], &[]);
+        draw(&old, &values);
+
+        // Narrower and shallower brains, as after "Load brain"
+        draw(&net(vec![5, 4, 1], &mut rng), &values);
+        draw(&net(vec![5, 1], &mut rng), &values);
+        draw(&net(vec![5, 8, 8, 1], &mut rng), &values);
+    }
+}
@@ -1,8 +1,8 @@
 use rand::Rng;
  
-pub fn random_pipe_position() -> (f32, f32) {
-    let mut rng = rand::thread_rng();
+// `gap_offset` is the distance between the centres of the two pipes
+pub fn random_pipe_position(rng: &mut impl Rng, gap_offset: f32) -> (f32, f32) {
     let lower = -rng.gen_range(70.0..280.0); // Lower pipe position (negative)
  
-    (lower, lower + 450.0)
+    (lower, lower + gap_offset)
 }
\ No newline at end of file
@@ -0,0 +1,575 @@
+// Plain-Rust game core: the birds, the pipe course and the rules that move them.
+// Nothing in here knows about Bevy. The systems mirror a FlappyWorld onto
+// sprites, and anything else (the trainer, tests, other learning code) can
+// drive it directly through `reset` and `step`, which both hand back what the
+// birds see.
+
+use std::fmt;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{FIXED_TIMESTEP, WINDOW_WIDTH};
+use crate::fitness::{FitnessTerms, FitnessWeights};
+use crate::utils::random_pipe_position;
+
+pub const NUM_PIPE_PAIRS: usize = 5;
+pub const NUM_OBSERVATIONS: usize = 5;
+
+// Birds never move horizontally, the pipes come to them
+pub const BIRD_X: f32 = 0.0;
+
+const CEILING_Y: f32 = 260.0;
+// Ground sprite is at -250 and 112 high, the bird is ~23 high
+const GROUND_COLLISION_Y: f32 = -250.0 + 112.0 / 2.0 + 23.0 / 2.0;
+
+const BIRD_WIDTH: f32 = 34.0;
+const BIRD_HEIGHT: f32 = 24.0;
+const PIPE_WIDTH: f32 = 52.0;
+const PIPE_HEIGHT: f32 = 320.0;
+
+const PIPE_SPEED: f32 = 150.0;
+const FIRST_PIPE_X: f32 = 400.0;
+
+// The tunable part of the rules, see `Config`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldParams {
+    pub gravity: f32,
+    // Upwards speed right after a flap
+    pub flap_velocity: f32,
+    // Horizontal distance between pipe pairs
+    pub pipe_spacing: f32,
+    // Vertical distance between the centres of the lower and upper pipe
+    pub pipe_gap_offset: f32,
+}
+
+impl Default for WorldParams {
+    fn default() -> Self {
+        Self {
+            gravity: 9.8 * 150.0,
+            flap_velocity: 300.0,
+            pipe_spacing: 200.0,
+            pipe_gap_offset: 450.0,
+        }
+    }
+}
+
+impl WorldParams {
+    pub fn validate(&self) -> Result<(), String> {
+        let more_than = |name: &str, value: f32, min: f32| {
+            if value.is_finite() && value > min {
+                Ok(())
+            } else {
+                Err(format!("{} must be more than {}, got {}", name, min, value))
+            }
+        };
+
+        more_than("gravity", self.gravity, 0.0)?;
+        more_than("flap_velocity", self.flap_velocity, 0.0)?;
+        // Closer than that and pipes overlap
+        more_than("pipe_spacing", self.pipe_spacing, PIPE_WIDTH)?;
+        // Anything smaller leaves no gap a bird fits through
+        more_than("pipe_gap_offset", self.pipe_gap_offset, PIPE_HEIGHT + BIRD_HEIGHT)
+    }
+}
+
+// What a bird's brain sees, see `FlappyWorld::observe`
+pub type Observation = [f32; NUM_OBSERVATIONS];
+
+// Actions the world can't take
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WorldError {
+    // One action per bird is needed
+    WrongActionCount { expected: usize, found: usize },
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorldError::WrongActionCount { expected, found } => {
+                write!(f, "World has {} birds, got {} actions", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BirdState {
+    pub y: f32,
+    pub velocity: f32,
+    pub alive: bool,
+    // Pipes this bird cleared since the last reset
+    pub score: u32,
+    // Sum of all rewards this bird got since the last reset
+    pub fitness: f32,
+    // What the fitness is made of
+    pub terms: FitnessTerms,
+}
+
+impl Default for BirdState {
+    fn default() -> Self {
+        Self {
+            y: 0.0,
+            velocity: 0.0,
+            alive: true,
+            score: 0,
+            fitness: 0.0,
+            terms: FitnessTerms::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PipePair {
+    // Centre of both pipes, they are 52 wide and 320 high
+    pub x: f32,
+    pub lower_y: f32,
+    pub upper_y: f32,
+    // The birds have gone past it
+    pub passed: bool,
+}
+
+// Serializable down to the RNG state so training checkpoints can stop and
+// resume mid-course
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlappyWorld {
+    birds: Vec<BirdState>,
+    pipes: Vec<PipePair>,
+    score: u32,
+    ticks: u64,
+    seed: u64,
+    rng: ChaCha8Rng,
+    params: WorldParams,
+    fitness: FitnessWeights,
+    // What every bird collects during a step, kept to not allocate each tick
+    #[serde(skip)]
+    tick_terms: Vec<FitnessTerms>,
+}
+
+impl FlappyWorld {
+    pub fn new(num_birds: usize, seed: u64, params: WorldParams) -> Self {
+        let mut world = Self {
+            birds: vec![BirdState::default(); num_birds],
+            pipes: Vec::with_capacity(NUM_PIPE_PAIRS),
+            score: 0,
+            ticks: 0,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            params,
+            fitness: FitnessWeights::default(),
+            tick_terms: Vec::new(),
+        };
+        world.reset(seed);
+        world
+    }
+
+    // Rewards the birds with `fitness` instead of the default weights
+    pub fn with_fitness(mut self, fitness: FitnessWeights) -> Self {
+        self.fitness = fitness;
+        self
+    }
+
+    // Puts every bird back at the start and lays out a new course from `seed`.
+    // Returns the first observation of each bird.
+    pub fn reset(&mut self, seed: u64) -> Vec<Observation> {
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.score = 0;
+        self.ticks = 0;
+
+        self.birds.fill(BirdState::default());
+
+        self.pipes.clear();
+        for i in 0..NUM_PIPE_PAIRS {
+            let (lower_y, upper_y) = random_pipe_position(&mut self.rng, self.params.pipe_gap_offset);
+            self.pipes.push(PipePair {
+                x: FIRST_PIPE_X + i as f32 * self.params.pipe_spacing,
+                lower_y,
+                upper_y,
+                passed: false,
+            });
+        }
+
+        self.observations()
+    }
+
+    // Advances the world by one fixed tick. `actions[i]` makes bird `i` flap.
+    // Returns what every bird sees after the tick, what it got for it and
+    // whether every bird is dead. Use `step_into` in hot loops.
+    pub fn step(&mut self, actions: &[bool]) -> Result<(Vec<Observation>, Vec<f32>, bool), WorldError> {
+        let (mut observations, mut rewards) = (Vec::new(), Vec::new());
+        let done = self.step_into(actions, &mut observations, &mut rewards)?;
+        Ok((observations, rewards, done))
+    }
+
+    // Like `step`, into buffers kept by the caller so stepping doesn't
+    // allocate: `observations[i]` is set to what bird `i` sees after the tick
+    // and `rewards[i]` to what it got for it
+    pub fn step_into(
+        &mut self,
+        actions: &[bool],
+        observations: &mut Vec<Observation>,
+        rewards: &mut Vec<f32>,
+    ) -> Result<bool, WorldError> {
+        if actions.len() != self.birds.len() {
+            return Err(WorldError::WrongActionCount { expected: self.birds.len(), found: actions.len() });
+        }
+
+        let dt = FIXED_TIMESTEP as f32;
+        let mut terms = std::mem::take(&mut self.tick_terms);
+        terms.clear();
+        terms.resize(self.birds.len(), FitnessTerms::default());
+
+        // Rewards for this tick are based on where the birds were when they acted
+        for (i, &flap) in actions.iter().enumerate() {
+            if !self.birds[i].alive {
+                continue;
+            }
+
+            terms[i].survival = 1.0;
+            terms[i].gap_alignment = self.precision_bonus(i);
+            if flap {
+                terms[i].flaps = 1.0;
+                self.birds[i].velocity = self.params.flap_velocity;
+            }
+        }
+
+        let gravity = self.params.gravity;
+        for (bird, terms) in self.birds.iter_mut().zip(terms.iter_mut()).filter(|(b, _)| b.alive) {
+            bird.y = (bird.y + bird.velocity * dt).min(CEILING_Y);
+            bird.velocity -= gravity * dt;
+            bird.y += bird.velocity * dt;
+
+            if bird.y < GROUND_COLLISION_Y {
+                bird.y = GROUND_COLLISION_Y;
+                bird.velocity = 0.0;
+                bird.alive = false;
+                terms.deaths = 1.0;
+            }
+        }
+
+        self.move_pipes(dt);
+
+        for (i, terms) in terms.iter_mut().enumerate() {
+            if self.birds[i].alive && self.hits_pipe(self.birds[i].y) {
+                self.birds[i].alive = false;
+                terms.deaths = 1.0;
+            }
+        }
+
+        // Every bird still alive when a pipe goes past clears it. They all
+        // sit at BIRD_X, so that's the same tick for each of them.
+        for pipe in self.pipes.iter_mut().filter(|p| p.x < BIRD_X && !p.passed) {
+            pipe.passed = true;
+            for (bird, terms) in self.birds.iter_mut().zip(terms.iter_mut()).filter(|(b, _)| b.alive) {
+                bird.score += 1;
+                terms.pipes += 1.0;
+            }
+        }
+        self.score = self.birds.iter().map(|b| b.score).max().unwrap_or(0);
+
+        rewards.clear();
+        for (bird, terms) in self.birds.iter_mut().zip(terms.iter()) {
+            let reward = self.fitness.fitness(terms);
+            bird.fitness += reward;
+            bird.terms.add(terms);
+            rewards.push(reward);
+        }
+        self.tick_terms = terms;
+        self.ticks += 1;
+
+        observations.clear();
+        observations.extend((0..self.birds.len()).map(|i| self.observe(i)));
+
+        Ok(self.alive_count() == 0)
+    }
+
+    // Brain inputs for one bird, all mapped into 0..1:
+    // 1. Bird Y
+    // 2. Gap centre Y of the next pipe
+    // 3. Distance to the next pipe
+    // 4. Velocity
+    // 5. Bird Y relative to the gap centre
+    pub fn observe(&self, bird: usize) -> Observation {
+        let bird_state = &self.birds[bird];
+        let pipe = self.next_pipe();
+        let gap_center_y = (pipe.upper_y + pipe.lower_y) / 2.0;
+        let dist_to_pipe_x = pipe.x - BIRD_X;
+
+        [
+            map_range(bird_state.y, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
+            map_range(gap_center_y, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
+            // Expand range to -50.0 to account for when bird is crossing the pipe
+            map_range(dist_to_pipe_x, -50.0, 500.0, 0.0, 1.0).clamp(0.0, 1.0),
+            // Expand range to -1000.0 to capture terminal velocity (falling fast)
+            map_range(bird_state.velocity, -1000.0, 500.0, 0.0, 1.0).clamp(0.0, 1.0),
+            map_range(bird_state.y - gap_center_y, -300.0, 300.0, 0.0, 1.0).clamp(0.0, 1.0),
+        ]
+    }
+
+    pub fn observations(&self) -> Vec<Observation> {
+        (0..self.birds.len()).map(|i| self.observe(i)).collect()
+    }
+
+    pub fn birds(&self) -> &[BirdState] {
+        &self.birds
+    }
+
+    pub fn pipes(&self) -> &[PipePair] {
+        &self.pipes
+    }
+
+    pub fn alive_count(&self) -> usize {
+        self.birds.iter().filter(|b| b.alive).count()
+    }
+
+    // Pipes the leading bird cleared since the last reset
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    // Seed of the current course
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn params(&self) -> &WorldParams {
+        &self.params
+    }
+
+    pub fn fitness_weights(&self) -> &FitnessWeights {
+        &self.fitness
+    }
+
+    // The pipe the birds have to get through next, i.e. the closest one they
+    // haven't completely flown past yet
+    fn next_pipe(&self) -> &PipePair {
+        self.pipes
+            .iter()
+            .filter(|p| p.x - BIRD_X + PIPE_WIDTH > 0.0)
+            .min_by(|a, b| a.x.total_cmp(&b.x))
+            .unwrap_or(&self.pipes[0])
+    }
+
+    // Reward staying close to the centre of the gap, only when close to the
+    // pipe to encourage alignment. Up to 1.0 per tick.
+    fn precision_bonus(&self, bird: usize) -> f32 {
+        let pipe = self.next_pipe();
+        let dist_to_pipe_x = pipe.x - BIRD_X;
+        if dist_to_pipe_x >= 100.0 || dist_to_pipe_x <= -50.0 {
+            return 0.0;
+        }
+
+        let gap_center_y = (pipe.upper_y + pipe.lower_y) / 2.0;
+        let vertical_dist = (self.birds[bird].y - gap_center_y).abs();
+        if vertical_dist < 50.0 {
+            (50.0 - vertical_dist) / 50.0
+        } else {
+            0.0
+        }
+    }
+
+    fn move_pipes(&mut self, dt: f32) {
+        let utmost_right_pipe = self
+            .pipes
+            .iter()
+            .map(|p| p.x)
+            .fold(f32::MIN, f32::max);
+        let new_pipe_position = utmost_right_pipe + self.params.pipe_spacing;
+        let out_of_screen_x = (-WINDOW_WIDTH / 2.) - 26.;
+
+        for pipe in self.pipes.iter_mut() {
+            pipe.x -= PIPE_SPEED * dt;
+
+            if pipe.x < out_of_screen_x {
+                let (lower_y, upper_y) = random_pipe_position(&mut self.rng, self.params.pipe_gap_offset);
+                pipe.x = new_pipe_position;
+                pipe.lower_y = lower_y;
+                pipe.upper_y = upper_y;
+                pipe.passed = false;
+            }
+        }
+    }
+
+    fn hits_pipe(&self, bird_y: f32) -> bool {
+        let overlaps = |pipe_x: f32, pipe_y: f32| {
+            let collision_x = BIRD_X + BIRD_WIDTH / 2.0 > pipe_x - PIPE_WIDTH / 2.0
+                && BIRD_X - BIRD_WIDTH / 2.0 < pipe_x + PIPE_WIDTH / 2.0;
+            let collision_y = bird_y + BIRD_HEIGHT / 2.0 > pipe_y - PIPE_HEIGHT / 2.0
+                && bird_y - BIRD_HEIGHT / 2.0 < pipe_y + PIPE_HEIGHT / 2.0;
+
+            collision_x && collision_y
+        };
+
+        self.pipes
+            .iter()
+            .any(|p| overlaps(p.x, p.upper_y) || overlaps(p.x, p.lower_y))
+    }
+}
+
+fn map_range(val: f32, in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> f32 {
+    (val - in_min) * (out_max - out_min) / (in_max - in_min) + out_min
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    // Hovers just under the centre of the next gap, good for quite a few pipes
+    fn follows_gap(world: &FlappyWorld, bird: usize) -> bool {
+        let observation = world.observe(bird);
+        observation[4] < 0.45 && observation[3] < 0.6
+    }
+
+    #[test]
+    fn same_seed_and_actions_give_the_same_run() {
+        let mut a = FlappyWorld::new(4, 99, WorldParams::default());
+        let mut b = FlappyWorld::new(4, 99, WorldParams::default());
+        let mut actions_rng = ChaCha8Rng::seed_from_u64(1);
+        // `b` steps into buffers, which has to come out the same as `step`
+        let (mut observations_b, mut rewards_b) = (Vec::new(), Vec::new());
+
+        for _ in 0..2000 {
+            let actions: Vec<bool> = (0..4).map(|_| actions_rng.gen_bool(0.08)).collect();
+            let (observations_a, rewards_a, done) = a.step(&actions).unwrap();
+            assert_eq!(done, b.step_into(&actions, &mut observations_b, &mut rewards_b).unwrap());
+            assert_eq!(observations_a, observations_b);
+            assert_eq!(observations_a, a.observations());
+            assert_eq!(rewards_a, rewards_b);
+            assert_eq!(a.birds(), b.birds());
+            assert_eq!(a.pipes(), b.pipes());
+            if done {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn a_wrong_number_of_actions_is_an_error() {
+        let mut world = FlappyWorld::new(3, 7, WorldParams::default());
+        let fresh = world.clone();
+
+        assert_eq!(world.step(&[true]), Err(WorldError::WrongActionCount { expected: 3, found: 1 }));
+        assert_eq!(
+            world.step(&[false; 4]).map(|_| ()),
+            Err(WorldError::WrongActionCount { expected: 3, found: 4 })
+        );
+        // Nothing moved
+        assert_eq!(world.ticks(), 0);
+        assert_eq!(world.birds(), fresh.birds());
+    }
+
+    #[test]
+    fn a_bird_that_never_flaps_dies_on_the_ground() {
+        let mut world = FlappyWorld::new(1, 3, WorldParams::default());
+        let (mut observations, mut rewards) = (Vec::new(), Vec::new());
+        while !world.step_into(&[false], &mut observations, &mut rewards).unwrap() {
+            assert!(world.ticks() < 600, "Bird never hit the ground");
+        }
+
+        let bird = &world.birds()[0];
+        assert_eq!(bird.y, GROUND_COLLISION_Y);
+        assert_eq!(bird.terms.deaths, 1.0);
+        // Long before the first pipe gets anywhere near it
+        assert!(world.pipes().iter().all(|p| p.x > BIRD_X + PIPE_WIDTH));
+    }
+
+    #[test]
+    fn the_ceiling_holds_a_bird_until_a_pipe_kills_it() {
+        let mut world = FlappyWorld::new(1, 3, WorldParams::default());
+        let (mut observations, mut rewards) = (Vec::new(), Vec::new());
+        // The ceiling is applied before the last bit of the tick's movement
+        let ceiling = CEILING_Y + world.params.flap_velocity * FIXED_TIMESTEP as f32;
+        // The first pipe is still far off
+        for _ in 0..60 {
+            world.step_into(&[true], &mut observations, &mut rewards).unwrap();
+            assert!(world.birds()[0].alive);
+            assert!(world.birds()[0].y <= ceiling);
+        }
+
+        while world.birds()[0].alive {
+            world.step_into(&[true], &mut observations, &mut rewards).unwrap();
+            assert!(world.ticks() < 600, "Bird never hit a pipe");
+        }
+        // Killed by the upper pipe it ran into
+        let bird_y = world.birds()[0].y;
+        let pipe = world.next_pipe();
+        assert!((pipe.x - BIRD_X).abs() < (PIPE_WIDTH + BIRD_WIDTH) / 2.0);
+        assert!(bird_y > (pipe.lower_y + pipe.upper_y) / 2.0);
+        assert_eq!(world.birds()[0].terms.deaths, 1.0);
+    }
+
+    #[test]
+    fn pipes_kill_birds_in_them_but_not_in_the_gap() {
+        let mut world = FlappyWorld::new(3, 5, WorldParams::default());
+        let pipe = world.pipes[0].clone();
+        for other in world.pipes.iter_mut().skip(1) {
+            other.x += 1000.0;
+        }
+        world.pipes[0].x = BIRD_X;
+
+        world.birds[0].y = pipe.lower_y;
+        world.birds[1].y = pipe.upper_y - PIPE_HEIGHT / 2.0 + 10.0;
+        world.birds[2].y = (pipe.lower_y + pipe.upper_y) / 2.0;
+        world.step_into(&[false, false, false], &mut Vec::new(), &mut Vec::new()).unwrap();
+
+        let alive: Vec<bool> = world.birds().iter().map(|b| b.alive).collect();
+        assert_eq!(alive, [false, false, true]);
+    }
+
+    #[test]
+    fn every_cleared_pipe_scores_once_for_each_surviving_bird() {
+        let mut world = FlappyWorld::new(3, 11, WorldParams::default());
+        let (mut observations, mut rewards) = (Vec::new(), Vec::new());
+
+        while world.score() < 5 {
+            let passed_before: Vec<bool> = world.pipes().iter().map(|p| p.passed).collect();
+            let score_before = world.score();
+            // Birds 0 and 1 fly the same way, bird 2 falls
+            let actions = [follows_gap(&world, 0), follows_gap(&world, 1), false];
+            world.step_into(&actions, &mut observations, &mut rewards).unwrap();
+
+            let cleared = world.pipes().iter().zip(&passed_before).filter(|(p, &before)| p.passed && !before);
+            assert_eq!(world.score() - score_before, cleared.count() as u32);
+            assert!(world.ticks() < 5000, "The gap follower got stuck");
+            assert!(world.birds()[0].alive, "The gap follower crashed");
+        }
+
+        let birds = world.birds();
+        assert_eq!(birds[0].score, 5);
+        assert_eq!(birds[1].score, 5);
+        assert_eq!(birds[2].score, 0);
+        assert_eq!(birds[0].terms.pipes, 5.0);
+        // Both survivors got the pipe rewards
+        assert_eq!(birds[0].fitness, birds[1].fitness);
+        assert!(birds[0].fitness > 5.0 * FitnessWeights::default().pipes);
+    }
+
+    #[test]
+    fn reset_restores_the_initial_state() {
+        let fresh = FlappyWorld::new(2, 21, WorldParams::default());
+        let mut world = FlappyWorld::new(2, 8, WorldParams::default());
+        let (mut observations, mut rewards) = (Vec::new(), Vec::new());
+        for tick in 0..300 {
+            world.step_into(&[tick % 15 == 0, follows_gap(&world, 1)], &mut observations, &mut rewards).unwrap();
+        }
+
+        let observations = world.reset(21);
+        assert_eq!(observations, fresh.observations());
+        assert_eq!(world.birds(), fresh.birds());
+        assert_eq!(world.pipes(), fresh.pipes());
+        assert_eq!(world.score(), 0);
+        assert_eq!(world.ticks(), 0);
+        assert_eq!(world.seed(), 21);
+    }
+}